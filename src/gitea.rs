@@ -1,6 +1,9 @@
-use crate::{get_runtime_folder, Result, DOCKER_NETWORK_NAME};
-use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
-use std::{collections::HashMap, fs::create_dir_all};
+mod oauth2;
+
+pub use oauth2::GiteaOAuth2App;
+
+use crate::{get_runtime_folder, tls::TlsCert, Result, DOCKER_NETWORK_NAME};
+use std::{collections::HashMap, fs::create_dir_all, sync::OnceLock};
 use testcontainers::{
     core::{CmdWaitFor, ContainerPort, ContainerState, ExecCommand, Mount, WaitFor},
     runners::AsyncRunner as _,
@@ -37,9 +40,11 @@ pub struct Gitea {
     admin_key: Option<String>,
     admin_commands: Vec<Vec<String>>,
     config_env: HashMap<String, String>,
-    tls: Option<GiteaTlsCert>,
+    tls: Option<GiteaTls>,
     hostname: String,
     repos: Vec<GiteaRepo>,
+    oauth2_provider: bool,
+    reverse_proxy_auth: bool,
 }
 
 impl Default for Gitea {
@@ -58,6 +63,8 @@ impl Default for Gitea {
             tls: None,
             hostname: "localhost".to_string(),
             repos: vec![],
+            oauth2_provider: false,
+            reverse_proxy_auth: false,
         }
     }
 }
@@ -97,6 +104,7 @@ impl Image for Gitea {
             ("GITEA_HTTP_PORT", GITEA_HTTP_PORT.as_u16().to_string()),
             ("PROTOCOL", self.protocol().to_string()),
             ("HOSTNAME", self.hostname.clone()),
+            ("ROOT_URL", self.root_url()),
         ]);
 
         let mut app_ini =
@@ -106,7 +114,7 @@ impl Image for Gitea {
         // - store TLS cert and key to the config folder,
         // - add TLS-related config to app.ini
         let config_folder = self.config_folder.source().unwrap();
-        if let Some(tls_config) = &self.tls {
+        if let Some(tls_config) = self.tls_cert() {
             tls_config.store_to(config_folder).unwrap();
 
             let tls_config = format!(
@@ -119,6 +127,12 @@ impl Image for Gitea {
             );
             app_ini.push_str(&tls_config);
         }
+        if self.oauth2_provider {
+            app_ini.push_str(oauth2::OAUTH2_PROVIDER_CONFIG);
+        }
+        if self.reverse_proxy_auth {
+            app_ini.push_str(oauth2::REVERSE_PROXY_AUTH_CONFIG);
+        }
         std::fs::write(format!("{}/app.ini", config_folder), app_ini.as_bytes()).unwrap();
 
         mounts.into_iter()
@@ -190,9 +204,11 @@ impl Gitea {
         }
     }
 
+    /// Generated TLS certificate is issued for this hostname too.
     pub fn with_hostname(self, hostname: impl Into<String>) -> Self {
         Self {
             hostname: hostname.into(),
+            tls: self.tls.map(GiteaTls::reset),
             ..self
         }
     }
@@ -216,22 +232,31 @@ impl Gitea {
         Self { admin_commands, ..self }
     }
 
+    /// Enables TLS with self-signed certificate which is generated for the hostname when container is built.
     pub fn with_tls(self, enabled: bool) -> Self {
         Self {
-            tls: if enabled { Some(GiteaTlsCert::default()) } else { None },
+            tls: if enabled { Some(GiteaTls::generated()) } else { None },
             ..self
         }
     }
 
     pub fn with_tls_certs(self, cert: impl Into<String>, key: impl Into<String>) -> Self {
         Self {
-            tls: Some(GiteaTlsCert::from_pem(cert.into(), key.into())),
+            tls: Some(GiteaTls::Custom(GiteaTlsCert::from_pem(cert.into(), key.into()))),
             ..self
         }
     }
 
+    /// CA of the generated certificate, it's generated on the first call if container isn't built yet.
     pub fn tls_ca(&self) -> Option<&str> {
-        self.tls.as_ref().and_then(|t| t.ca())
+        self.tls_cert().and_then(|t| t.ca())
+    }
+
+    fn tls_cert(&self) -> Option<&GiteaTlsCert> {
+        match self.tls.as_ref()? {
+            GiteaTls::Generated(cert) => Some(cert.get_or_init(|| GiteaTlsCert::new(self.hostname.clone()))),
+            GiteaTls::Custom(cert) => Some(cert),
+        }
     }
 
     fn create_admin_user_cmd(&self) -> Vec<String> {
@@ -312,13 +337,44 @@ impl Gitea {
         }
     }
 
+    fn root_url(&self) -> String {
+        // OAuth2 issuer should be reachable from other containers of the network
+        if self.oauth2_provider {
+            format!("{}://{}:{}/", self.protocol(), self.hostname, GITEA_HTTP_PORT.as_u16())
+        } else {
+            format!("{}://{}/", self.protocol(), self.hostname)
+        }
+    }
+
     fn api_url(&self, api: &str) -> String {
         let api = api.strip_prefix('/').unwrap_or(api);
-        format!(
-            "{}://localhost:{}/api/v1/{api}",
-            self.protocol(),
-            GITEA_HTTP_PORT.as_u16()
-        )
+        self.local_url(&format!("api/v1/{api}"))
+    }
+
+    fn local_url(&self, path: &str) -> String {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        format!("{}://localhost:{}/{path}", self.protocol(), GITEA_HTTP_PORT.as_u16())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum GiteaTls {
+    /// Self-signed certificate for the hostname, it's generated once the hostname is final.
+    Generated(OnceLock<GiteaTlsCert>),
+    Custom(GiteaTlsCert),
+}
+
+impl GiteaTls {
+    fn generated() -> Self {
+        Self::Generated(OnceLock::new())
+    }
+
+    /// Drops generated certificate, so it's issued again for the new hostname.
+    fn reset(self) -> Self {
+        match self {
+            Self::Generated(_) => Self::generated(),
+            custom => custom,
+        }
     }
 }
 
@@ -337,28 +393,14 @@ impl Default for GiteaTlsCert {
 
 impl GiteaTlsCert {
     pub fn new(hostname: impl Into<String>) -> Self {
-        let ca_key = KeyPair::generate().unwrap();
-        let mut ca_cert = CertificateParams::new(vec!["Gitea root CA".to_string()]).unwrap();
-        ca_cert.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
-        let ca_cert = ca_cert.self_signed(&ca_key).unwrap();
-
         let mut hostnames = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
         let hostname = hostname.into();
         if hostname != "localhost" {
             hostnames.insert(0, hostname);
         }
 
-        let key = KeyPair::generate().unwrap();
-        let cert = CertificateParams::new(hostnames)
-            .unwrap()
-            .signed_by(&key, &ca_cert, &ca_key)
-            .unwrap();
-
-        Self {
-            cert: cert.pem(),
-            key: key.serialize_pem(),
-            ca: Some(ca_cert.pem()),
-        }
+        let TlsCert { cert, key, ca } = TlsCert::new("Gitea root CA", hostnames);
+        Self { cert, key, ca }
     }
 
     pub fn from_pem(cert: impl Into<String>, key: impl Into<String>) -> Self {
@@ -0,0 +1,226 @@
+use super::{Gitea, GiteaTls};
+use crate::{exec_cmd, Error, Result};
+use serde::Deserialize;
+use testcontainers::ContainerAsync;
+
+pub(super) const OAUTH2_PROVIDER_CONFIG: &str = "\n[oauth2]\nENABLED = true\n";
+pub(super) const REVERSE_PROXY_AUTH_CONFIG: &str = "\n[service]\nENABLE_REVERSE_PROXY_AUTHENTICATION = true\n";
+
+/// Redirect URI of the registered applications, it's never opened since authorization code is taken
+/// from the redirect location.
+const OAUTH2_REDIRECT_URI: &str = "http://127.0.0.1/callback";
+const OAUTH2_SCOPE: &str = "openid profile email groups";
+const OAUTH2_STATE: &str = "testcontainers";
+const REVERSE_PROXY_AUTH_HEADER: &str = "X-WEBAUTH-USER";
+const CSRF_COOKIE_NAME: &str = "_csrf";
+
+/// OAuth2 application (OIDC client) registered in Gitea.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GiteaOAuth2App {
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+impl Gitea {
+    /// Turns on OAuth2 (OpenID Connect) provider: issuer URL includes container port to be reachable from
+    /// other containers of the same network (see [`Gitea::oidc_issuer_url`]) and TLS is enabled.
+    /// Certificates set by [`Gitea::with_tls_certs`] are kept, otherwise certificate is generated for the hostname.
+    pub fn with_oauth2_provider(self) -> Self {
+        Self {
+            tls: Some(self.tls.unwrap_or_else(GiteaTls::generated)),
+            oauth2_provider: true,
+            ..self
+        }
+    }
+
+    /// Enables reverse proxy authentication: every request with `X-WEBAUTH-USER` header is trusted
+    /// as made by that user, so enable it only if the server isn't reachable by untrusted clients.
+    /// It's required by [`Gitea::id_token`] to authorize applications on behalf of users.
+    pub fn with_reverse_proxy_authentication(self, enabled: bool) -> Self {
+        Self {
+            reverse_proxy_auth: enabled,
+            ..self
+        }
+    }
+
+    /// OIDC issuer URL, like `https://git-server:3000/`, CA certificate is [`Gitea::tls_ca`].
+    pub fn oidc_issuer_url(&self) -> String {
+        self.root_url()
+    }
+
+    /// Registers confidential OAuth2 application owned by the admin account.
+    pub async fn create_oauth2_app(container: &ContainerAsync<Gitea>, name: impl AsRef<str>) -> Result<GiteaOAuth2App> {
+        let gitea = container.image();
+        // Grant page isn't shown for the application, so users authorize it right away
+        let body = serde_json::json!({
+            "name": name.as_ref(),
+            "redirect_uris": [OAUTH2_REDIRECT_URI],
+            "confidential_client": true,
+            "skip_secondary_authorization": true,
+        });
+        let cmd = vec![
+            "curl".to_string(),
+            "-sSfk".to_string(),
+            "-X".to_string(),
+            "POST".to_string(),
+            "-H".to_string(),
+            "Content-Type: application/json".to_string(),
+            "-u".to_string(),
+            format!("{}:{}", gitea.admin_username, gitea.admin_password),
+            "-d".to_string(),
+            body.to_string(),
+            gitea.api_url("/user/applications/oauth2"),
+        ];
+
+        let response = exec_cmd(container, cmd).await?;
+        serde_json::from_slice(&response)
+            .map_err(|e| Error::RuntimeConfig(format!("invalid OAuth2 application response: {e}")))
+    }
+
+    /// Authorizes the application on behalf of the existing user and exchanges authorization code for tokens,
+    /// returns ID token with `preferred_username`, `email` and `groups` claims.
+    /// Requires [`Gitea::with_reverse_proxy_authentication`] to act as the user.
+    pub async fn id_token(
+        container: &ContainerAsync<Gitea>,
+        app: &GiteaOAuth2App,
+        username: impl AsRef<str>,
+    ) -> Result<String> {
+        let gitea = container.image();
+        let username = username.as_ref();
+        if !gitea.reverse_proxy_auth {
+            return Err(Error::RuntimeConfig(
+                "reverse proxy authentication should be enabled to get ID tokens of users".into(),
+            ));
+        }
+        let cookies = format!("/tmp/oauth2-{username}.cookies");
+        let web_cmd = |args: Vec<String>| {
+            let mut cmd = [
+                "curl",
+                "-sSfk",
+                "-c",
+                cookies.as_str(),
+                "-b",
+                cookies.as_str(),
+                "-H",
+                &format!("{REVERSE_PROXY_AUTH_HEADER}: {username}"),
+                "-w",
+                "\n%{redirect_url}",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<String>>();
+            cmd.extend(args);
+            cmd
+        };
+        let params = |extra: &[(&str, &str)]| {
+            [
+                ("client_id", app.client_id.as_str()),
+                ("redirect_uri", OAUTH2_REDIRECT_URI),
+                ("scope", OAUTH2_SCOPE),
+                ("state", OAUTH2_STATE),
+            ]
+            .iter()
+            .chain(extra)
+            .flat_map(|(k, v)| ["--data-urlencode".to_string(), format!("{k}={v}")])
+            .collect::<Vec<String>>()
+        };
+
+        // Application without secondary authorization is redirected right away, otherwise it's granted
+        // with CSRF token from the session cookie
+        let mut authorize = vec!["-G".to_string(), gitea.local_url("/login/oauth/authorize")];
+        authorize.extend(params(&[("response_type", "code")]));
+        let mut location = redirect_location(&exec_cmd(container, web_cmd(authorize)).await?);
+        if authorization_code(&location).is_none() {
+            let jar = exec_cmd(container, ["cat", cookies.as_str()]).await?;
+            let csrf = cookie_value(&String::from_utf8_lossy(&jar), CSRF_COOKIE_NAME)
+                .ok_or_else(|| Error::RuntimeConfig(format!("can't authorize OAuth2 app as `{username}`")))?;
+            let mut grant = vec![
+                "-H".to_string(),
+                format!("X-Csrf-Token: {csrf}"),
+                gitea.local_url("/login/oauth/grant"),
+            ];
+            grant.extend(params(&[("granted", "true")]));
+            location = redirect_location(&exec_cmd(container, web_cmd(grant)).await?);
+        }
+        let code = authorization_code(&location)
+            .ok_or_else(|| Error::RuntimeConfig(format!("OAuth2 app isn't granted by `{username}`")))?;
+
+        let token = [
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", OAUTH2_REDIRECT_URI),
+            ("client_id", app.client_id.as_str()),
+            ("client_secret", app.client_secret.as_str()),
+        ]
+        .iter()
+        .flat_map(|(k, v)| ["--data-urlencode".to_string(), format!("{k}={v}")])
+        .collect::<Vec<String>>();
+        let cmd = ["curl".to_string(), "-sSfk".to_string()]
+            .into_iter()
+            .chain(token)
+            .chain([gitea.local_url("/login/oauth/access_token")]);
+        let response = exec_cmd(container, cmd).await?;
+        let response: TokenResponse = serde_json::from_slice(&response)
+            .map_err(|e| Error::RuntimeConfig(format!("invalid OAuth2 token response: {e}")))?;
+
+        response
+            .id_token
+            .ok_or_else(|| Error::RuntimeConfig("OAuth2 token response has no ID token".into()))
+    }
+}
+
+/// Redirect location written by `-w "\n%{redirect_url}"` after the body of `curl` output.
+fn redirect_location(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    output
+        .rsplit_once('\n')
+        .map(|(_, location)| location.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Cookie value from the `curl` cookie jar (Netscape format: tab-separated fields, name and value are the last).
+fn cookie_value(jar: &str, name: &str) -> Option<String> {
+    jar.lines().find_map(|line| {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        match fields[..] {
+            [.., cookie, value] if fields.len() == 7 && cookie == name => Some(value.to_string()),
+            _ => None,
+        }
+    })
+}
+
+fn authorization_code(location: &str) -> Option<String> {
+    let (_, query) = location.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|p| p.strip_prefix("code="))
+        .filter(|code| !code.is_empty())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_authorization_flow() {
+        let location = redirect_location(b"<form method=\"post\">\n</form>\n");
+        assert_eq!(location, "");
+        assert_eq!(authorization_code(&location), None);
+
+        let location = redirect_location(b"Found.\n\nhttp://127.0.0.1/callback?code=xyz&state=testcontainers");
+        assert_eq!(authorization_code(&location).as_deref(), Some("xyz"));
+
+        let jar = "# Netscape HTTP Cookie File\n\n\
+                   #HttpOnly_localhost\tFALSE\t/\tTRUE\t0\ti_like_gitea\tsession\n\
+                   localhost\tFALSE\t/\tTRUE\t0\t_csrf\tabc-123\n";
+        assert_eq!(cookie_value(jar, CSRF_COOKIE_NAME).as_deref(), Some("abc-123"));
+        assert_eq!(cookie_value(jar, "lang"), None);
+    }
+}
@@ -1,25 +1,96 @@
-use crate::{init_crypto_provider, Error, Result, DOCKER_NETWORK_NAME};
+mod audit;
+mod auth;
+mod cluster;
+mod cni;
+mod config;
+mod crds;
+mod diagnostics;
+mod helm;
+mod images;
+mod ingress;
+mod kubeconfig;
+mod manifests;
+mod namespace;
+mod network;
+mod oidc;
+mod port_forward;
+mod readiness;
+mod registries;
+mod snapshot;
+mod wait;
+mod webhook;
+
+pub use audit::{K3sAuditEvent, K3sAuditFilter, K3sAuditObjectRef, K3sAuditResponseStatus, K3S_AUDIT_LOG_FILE};
+pub use auth::{client_for_id_token, client_for_service_account, K3S_SERVER_TLS_FOLDER, MIN_SERVICE_ACCOUNT_TOKEN_TTL};
+pub use cluster::{K3sAgent, K3sCluster, K3sClusterHandle, K3S_CLUSTER_DEFAULT_AGENTS};
+pub use cni::K3sFlannelBackend;
+pub use config::K3sPodSecurityLevel;
+pub use crds::{apply_crds, K3sCrd, K3S_DEFAULT_CRDS_TIMEOUT};
+pub use diagnostics::{TestDiagnostics, K3S_DIAGNOSTICS_FOLDER};
+pub use helm::{
+    K3sHelmChart, K3S_DEFAULT_HELM_BACK_OFF_LIMIT, K3S_DEFAULT_HELM_INSTALL_TIMEOUT, K3S_HELM_CHARTS_NAMESPACE,
+};
+pub use images::K3S_AGENT_IMAGES_FOLDER;
+pub use ingress::K3S_INGRESS_TLS_SECRET;
+pub use kubeconfig::{kubeconfig_env, KUBECONFIG_ENV};
+pub use manifests::{K3S_DEFAULT_MANIFESTS_TIMEOUT, K3S_MANIFESTS_FOLDER};
+#[cfg(feature = "destructor")]
+pub(crate) use namespace::delete_test_namespaces;
+pub use namespace::{
+    TestNamespace, K3S_DEFAULT_NAMESPACE_DELETION_TIMEOUT, KEEP_FAILED_TEST_NAMESPACES, TEST_NAMESPACE_LABEL,
+    TEST_NAME_ANNOTATION,
+};
+pub use network::{create_docker_ipv6_network, K3sIpFamily, DOCKER_IPV6_NETWORK_NAME, DOCKER_IPV6_NETWORK_SUBNET};
+#[cfg(feature = "gitea")]
+pub use oidc::client_for_gitea_user;
+pub use port_forward::{port_forward, LocalForward, PortForwardTarget};
+pub use readiness::{K3sReadinessStage, K3S_DEFAULT_READINESS_TIMEOUT};
+pub use snapshot::K3S_SNAPSHOTS_FOLDER;
+pub use wait::{
+    has_condition, is_deleted, is_deployment_rolled_out, is_job_completed, is_pod_ready, is_pvc_bound, wait_for,
+};
+pub use webhook::{AdmissionWebhookServer, K3sAdmissionWebhook, K3sAdmissionWebhookKind, K3S_HOST_GATEWAY_ALIAS};
+
+use crate::{
+    exec_cmd, init_crypto_provider, tls::TlsCert, Error, Result, DOCKER_NETWORK_NAME, EXEC_EXIT_CODE_POLL_INTERVAL,
+};
+use config::K3sServerConfig;
 use kube::{
     config::{KubeConfigOptions, Kubeconfig},
     Config,
 };
-use std::{borrow::Cow, path::Path};
+use manifests::ManifestObject;
+use registries::K3sRegistries;
+use semver::{Version, VersionReq};
+use std::{
+    borrow::Cow,
+    future::Future,
+    path::Path,
+    sync::{OnceLock, RwLock},
+    thread,
+    time::Duration,
+};
 use testcontainers::{
-    core::{ContainerPort, Mount, WaitFor},
+    core::{ContainerPort, ContainerState, ExecCommand, Host, Mount, WaitFor},
     runners::AsyncRunner as _,
-    ContainerAsync, Image, ImageExt as _,
+    ContainerAsync, CopyToContainer, Image, ImageExt as _, TestcontainersError,
 };
+use tokio::runtime;
 
 pub const K3S_KUBECONFIG_PORT: u16 = 9443;
 
 pub const K3S_KUBE_API_PORT: ContainerPort = ContainerPort::Tcp(6443);
 pub const K3S_TRAEFIK_HTTP_PORT: ContainerPort = ContainerPort::Tcp(80);
+pub const K3S_TRAEFIK_HTTPS_PORT: ContainerPort = ContainerPort::Tcp(443);
 pub const K3S_RANCHER_WEBHOOK_PORT: ContainerPort = ContainerPort::Tcp(8443);
 
 pub const K3S_IMAGE_NAME: &str = "rancher/k3s";
 pub const K3S_DEFAULT_KUBE_VERSION: &str = "1.31";
 
-const RUNTIME_FOLDER_SUFFIX: &str = "k3s-runtime";
+const FIELD_MANAGER: &str = "testcontainers-modules";
+
+const K3S_CONFIG_FOLDER: &str = "/etc/rancher/k3s";
+const K3S_KUBECONFIG_FILE_NAME: &str = "k3s.yaml";
 const AVAILABLE_K3S_IMAGE_TAGS: [(&str, &str); 6] = [
     ("1.31", "v1.31.1-k3s1"),
     ("1.30", "v1.30.5-k3s1"),
@@ -29,42 +100,163 @@ const AVAILABLE_K3S_IMAGE_TAGS: [(&str, &str); 6] = [
     ("1.26", "v1.26.15-k3s1"),
 ];
 
+static BUILTIN_K3S_IMAGE_TAGS: OnceLock<Vec<K3sImageTag>> = OnceLock::new();
+static ADDED_K3S_IMAGE_TAGS: RwLock<Vec<K3sImageTag>> = RwLock::new(Vec::new());
+
+/// k3s server image, container start waits for the node controller and startup manifests only,
+/// so call [`K3s::wait_ready`] after start to check nodes, addons and other readiness stages.
 #[derive(Debug, Clone)]
 pub struct K3s {
-    kubeconfig_mount: Mount,
+    kubeconfig_mount: Option<Mount>,
     tag: String,
     features: K3sFeatures,
+    token: Option<String>,
+    manifest_files: Vec<(String, CopyToContainer)>,
+    image_archives: Vec<CopyToContainer>,
+    manifests: Vec<ManifestObject>,
+    manifests_timeout: Duration,
+    defer_manifests_wait: bool,
+    registries: K3sRegistries,
+    registries_files: Vec<CopyToContainer>,
+    readiness_stages: Vec<K3sReadinessStage>,
+    readiness_timeout: Duration,
+    server_config: K3sServerConfig,
+    config_files: Vec<CopyToContainer>,
+    ingress_tls: Option<TlsCert>,
+    cni_daemon_sets: Vec<(String, String)>,
 }
 
 impl Default for K3s {
     fn default() -> Self {
-        let build_out_dir = crate::get_runtime_folder().unwrap();
+        let features = K3sFeatures::default();
+        let server_config = K3sServerConfig::default();
         Self {
-            kubeconfig_mount: Mount::bind_mount(
-                format!("{build_out_dir}/{RUNTIME_FOLDER_SUFFIX}"),
-                "/etc/rancher/k3s/",
-            ),
+            kubeconfig_mount: None,
             tag: version_to_tag(K3S_DEFAULT_KUBE_VERSION).unwrap(),
-            features: K3sFeatures::default(),
+            config_files: server_config.files(&features),
+            features,
+            token: None,
+            manifest_files: vec![],
+            image_archives: vec![],
+            manifests: vec![],
+            manifests_timeout: K3S_DEFAULT_MANIFESTS_TIMEOUT,
+            defer_manifests_wait: false,
+            registries: K3sRegistries::default(),
+            registries_files: vec![],
+            readiness_stages: K3sReadinessStage::ALL.to_vec(),
+            readiness_timeout: K3S_DEFAULT_READINESS_TIMEOUT,
+            server_config,
+            ingress_tls: None,
+            cni_daemon_sets: vec![],
         }
     }
 }
 
+/// Adds image tags (like `v1.31.3-k3s1`) which are used to resolve kube versions
+/// that don't match any of the built-in tags, so defaults like `1.31` aren't changed.
+pub fn add_k3s_image_tags(tags: impl IntoIterator<Item = impl Into<String>>) -> Result<()> {
+    let tags = tags
+        .into_iter()
+        .map(|tag| K3sImageTag::parse(tag.into()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut table = ADDED_K3S_IMAGE_TAGS.write().unwrap();
+    for tag in tags {
+        if !table.contains(&tag) {
+            table.push(tag);
+        }
+    }
+
+    Ok(())
+}
+
+fn builtin_k3s_image_tags() -> &'static [K3sImageTag] {
+    BUILTIN_K3S_IMAGE_TAGS.get_or_init(|| {
+        AVAILABLE_K3S_IMAGE_TAGS
+            .iter()
+            .map(|(_, tag)| K3sImageTag::parse(*tag).unwrap())
+            .collect()
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct K3sImageTag {
+    tag: String,
+    version: Version,
+    pre_release: bool,
+    revision: u64,
+}
+
+impl K3sImageTag {
+    /// Parses tags in the `v<major>.<minor>.<patch>[-rc<N>]-k3s<revision>` form.
+    fn parse(tag: impl Into<String>) -> Result<Self> {
+        let tag = tag.into();
+        let invalid = || Error::RuntimeConfig(format!("'{tag}' is not a valid k3s image tag"));
+
+        let (base, suffix) = tag
+            .strip_prefix('v')
+            .unwrap_or(&tag)
+            .split_once('-')
+            .ok_or_else(invalid)?;
+        let (pre, revision) = suffix.rsplit_once("k3s").ok_or_else(invalid)?;
+        let revision = revision.parse::<u64>().map_err(|_| invalid())?;
+        let version = Version::parse(base).map_err(|_| invalid())?;
+
+        Ok(Self {
+            version,
+            pre_release: !pre.is_empty(),
+            revision,
+            tag: format!("v{}", tag.strip_prefix('v').unwrap_or(&tag)),
+        })
+    }
+}
+
 fn version_to_tag(version: impl Into<String>) -> Result<String> {
+    let added = ADDED_K3S_IMAGE_TAGS.read().unwrap();
+    resolve_tag(version, &[builtin_k3s_image_tags(), &added])
+}
+
+/// Resolves version against the tables: plain versions use the first table which has matching tag,
+/// so earlier tables take precedence, while requirements are matched against all tables merged.
+fn resolve_tag(version: impl Into<String>, tables: &[&[K3sImageTag]]) -> Result<String> {
     let version = version.into();
     let version = version.strip_prefix('v').map(String::from).unwrap_or(version);
     let version = if version.is_empty() || version == "latest" {
-        K3S_DEFAULT_KUBE_VERSION
+        K3S_DEFAULT_KUBE_VERSION.to_string()
+    } else {
+        version
+    };
+
+    // Full image tag is used as is
+    if let Ok(tag) = K3sImageTag::parse(version.as_str()) {
+        return Ok(tag.tag);
+    }
+
+    // Plain versions like `1.30` or `1.30.6` mean exact minor or patch release,
+    // everything else is treated as semver requirement like `>=1.29`
+    let is_plain_version = version.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let requirement = if is_plain_version {
+        format!("={version}")
     } else {
-        version.as_str()
+        version.clone()
     };
+    let requirement = VersionReq::parse(&requirement)
+        .map_err(|e| Error::RuntimeConfig(format!("Kube version '{version}' is not valid: {e}")))?;
 
-    AVAILABLE_K3S_IMAGE_TAGS
-        .iter()
-        .find(|(k, _)| *k == version)
-        .map(|(_, v)| *v)
+    let tag = if is_plain_version {
+        tables.iter().find_map(|table| latest_tag(table.iter(), &requirement))
+    } else {
+        latest_tag(tables.iter().flat_map(|table| table.iter()), &requirement)
+    };
+
+    tag.map(|tag| tag.tag.clone())
         .ok_or_else(|| Error::RuntimeConfig(format!("Kube version '{}' is not supported", version)))
-        .map(String::from)
+}
+
+/// Latest released tag which matches the requirement.
+fn latest_tag<'a>(tags: impl Iterator<Item = &'a K3sImageTag>, requirement: &VersionReq) -> Option<&'a K3sImageTag> {
+    tags.filter(|tag| !tag.pre_release && requirement.matches(&tag.version))
+        .max_by(|a, b| (&a.version, a.revision).cmp(&(&b.version, b.revision)))
 }
 
 #[derive(Debug, Clone)]
@@ -78,6 +270,7 @@ struct K3sFeatures {
     metrics_server: bool,
     helm_controller: bool,
     agent: bool,
+    cluster_init: bool,
 }
 
 impl Default for K3sFeatures {
@@ -92,46 +285,11 @@ impl Default for K3sFeatures {
             metrics_server: true,
             helm_controller: true,
             agent: true,
+            cluster_init: false,
         }
     }
 }
 
-impl IntoIterator for &K3sFeatures {
-    type Item = String;
-    type IntoIter = <Vec<String> as IntoIterator>::IntoIter;
-
-    fn into_iter(self) -> Self::IntoIter {
-        let mut cmd = vec![String::from("server"), format!("--snapshotter={}", self.snapshotter)];
-
-        if !self.traefik {
-            cmd.push("--disable=traefik".to_string());
-        }
-        if !self.service_lb {
-            cmd.push("--disable=servicelb".to_string());
-        }
-        if !self.coredns {
-            cmd.push("--disable=coredns".to_string());
-        }
-        if !self.agent {
-            cmd.push("--disable-agent".to_string());
-        }
-        if !self.helm_controller {
-            cmd.push("--disable-helm-controller".to_string());
-        }
-        if !self.local_storage {
-            cmd.push("--disable=local-storage".to_string());
-        }
-        if !self.metrics_server {
-            cmd.push("--disable=metrics-server".to_string());
-        }
-        if !self.network_policy {
-            cmd.push("--disable-network-policy".to_string());
-        }
-
-        cmd.into_iter()
-    }
-}
-
 impl Image for K3s {
     fn name(&self) -> &str {
         K3S_IMAGE_NAME
@@ -146,20 +304,47 @@ impl Image for K3s {
     }
 
     fn env_vars(&self) -> impl IntoIterator<Item = (impl Into<Cow<'_, str>>, impl Into<Cow<'_, str>>)> {
-        vec![(String::from("K3S_KUBECONFIG_MODE"), String::from("644"))]
+        let mut env = vec![(String::from("K3S_KUBECONFIG_MODE"), String::from("644"))];
+        if let Some(token) = &self.token {
+            env.push((String::from("K3S_TOKEN"), token.clone()));
+        }
+
+        env
     }
 
     fn mounts(&self) -> impl IntoIterator<Item = &Mount> {
-        vec![&self.kubeconfig_mount]
+        self.kubeconfig_mount.iter()
+    }
+
+    fn copy_to_sources(&self) -> impl IntoIterator<Item = &CopyToContainer> {
+        self.manifest_files
+            .iter()
+            .map(|(_, file)| file)
+            .chain(self.image_archives.iter())
+            .chain(self.registries_files.iter())
+            .chain(self.config_files.iter())
     }
 
     fn cmd(&self) -> impl IntoIterator<Item = impl Into<Cow<'_, str>>> {
-        &self.features
+        ["server"]
+    }
+
+    fn exec_after_start(&self, _cs: ContainerState) -> std::result::Result<Vec<ExecCommand>, TestcontainersError> {
+        if self.defer_manifests_wait {
+            return Ok(vec![]);
+        }
+
+        Ok(self.wait_for_manifests_cmd().into_iter().collect())
     }
 
     fn expose_ports(&self) -> &[ContainerPort] {
         if self.features.traefik {
-            &[K3S_KUBE_API_PORT, K3S_RANCHER_WEBHOOK_PORT, K3S_TRAEFIK_HTTP_PORT]
+            &[
+                K3S_KUBE_API_PORT,
+                K3S_RANCHER_WEBHOOK_PORT,
+                K3S_TRAEFIK_HTTP_PORT,
+                K3S_TRAEFIK_HTTPS_PORT,
+            ]
         } else {
             &[K3S_KUBE_API_PORT, K3S_RANCHER_WEBHOOK_PORT]
         }
@@ -167,148 +352,209 @@ impl Image for K3s {
 }
 
 impl K3s {
+    /// Accepts minor (`1.30`) or patch (`1.30.6`) versions, full image tags (`v1.30.6-k3s2`)
+    /// and semver requirements (`>=1.29`), panics if version can't be resolved.
     pub fn with_kube_version(self, version: impl Into<String>) -> Self {
-        Self {
-            tag: version_to_tag(version).unwrap(),
+        self.try_with_kube_version(version).unwrap()
+    }
+
+    pub fn try_with_kube_version(self, version: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            tag: version_to_tag(version)?,
             ..self
-        }
+        })
     }
 
     pub fn with_snapshotter(self, snapshotter: impl Into<String>) -> Self {
-        Self {
-            features: K3sFeatures {
-                snapshotter: snapshotter.into(),
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            snapshotter: snapshotter.into(),
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_traefik(self, traefik: bool) -> Self {
-        Self {
-            features: K3sFeatures {
-                traefik,
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            traefik,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_service_lb(self, service_lb: bool) -> Self {
-        Self {
-            features: K3sFeatures {
-                service_lb,
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            service_lb,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_coredns(self, coredns: bool) -> Self {
-        Self {
-            features: K3sFeatures {
-                coredns,
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            coredns,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_agent(self, agent: bool) -> Self {
-        Self {
-            features: K3sFeatures { agent, ..self.features },
-            ..self
-        }
+        let features = K3sFeatures {
+            agent,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_helm_controller(self, helm_controller: bool) -> Self {
-        Self {
-            features: K3sFeatures {
-                helm_controller,
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            helm_controller,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_local_storage(self, local_storage: bool) -> Self {
-        Self {
-            features: K3sFeatures {
-                local_storage,
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            local_storage,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_metrics_server(self, metrics_server: bool) -> Self {
-        Self {
-            features: K3sFeatures {
-                metrics_server,
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            metrics_server,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_network_policy(self, network_policy: bool) -> Self {
-        Self {
-            features: K3sFeatures {
-                network_policy,
-                ..self.features
-            },
-            ..self
-        }
+        let features = K3sFeatures {
+            network_policy,
+            ..self.features.clone()
+        };
+        self.with_features(features)
+    }
+
+    /// Uses embedded etcd instead of sqlite as datastore, it's required for snapshots.
+    pub fn with_cluster_init(self, cluster_init: bool) -> Self {
+        let features = K3sFeatures {
+            cluster_init,
+            ..self.features.clone()
+        };
+        self.with_features(features)
     }
 
     pub fn with_all_features(self, all_features: bool) -> Self {
+        let features = K3sFeatures {
+            traefik: all_features,
+            service_lb: all_features,
+            coredns: all_features,
+            helm_controller: all_features,
+            network_policy: all_features,
+            local_storage: all_features,
+            metrics_server: all_features,
+            ..self.features.clone()
+        };
+        self.with_features(features)
+    }
+
+    pub fn with_kubeconfig_folder(self, folder: impl Into<String>) -> Self {
         Self {
-            features: K3sFeatures {
-                traefik: all_features,
-                service_lb: all_features,
-                coredns: all_features,
-                helm_controller: all_features,
-                network_policy: all_features,
-                local_storage: all_features,
-                metrics_server: all_features,
-                ..self.features
-            },
+            kubeconfig_mount: Some(Mount::bind_mount(folder.into(), K3S_CONFIG_FOLDER)),
             ..self
         }
     }
 
-    pub fn with_kubeconfig_folder(self, folder: impl Into<String>) -> Self {
+    pub fn with_token(self, token: impl Into<String>) -> Self {
         Self {
-            kubeconfig_mount: Mount::bind_mount(folder.into(), "/etc/rancher/k3s/"),
+            token: Some(token.into()),
             ..self
         }
     }
 
+    /// Reads kubeconfig from the host folder, works only if kubeconfig folder is mounted.
     pub async fn get_kubeconfig(&self) -> Result<String> {
-        let kubeconfig_mount = self.kubeconfig_mount.source().unwrap();
-        let k3s_conf_file_path = Path::new(&kubeconfig_mount).join("k3s.yaml");
+        let kubeconfig_mount = self
+            .kubeconfig_mount
+            .as_ref()
+            .and_then(|m| m.source())
+            .ok_or_else(|| Error::RuntimeConfig("kubeconfig folder isn't mounted to the host".into()))?;
+        let k3s_conf_file_path = Path::new(&kubeconfig_mount).join(K3S_KUBECONFIG_FILE_NAME);
         tokio::fs::read_to_string(k3s_conf_file_path).await.map_err(Error::Io)
     }
 
+    /// Reads kubeconfig from the mounted host folder if it's configured, or from the container itself otherwise.
+    pub async fn read_kubeconfig(container: &ContainerAsync<K3s>) -> Result<String> {
+        if container.image().kubeconfig_mount.is_some() {
+            return container.image().get_kubeconfig().await;
+        }
+
+        let kubeconfig = exec_cmd(
+            container,
+            [
+                "cat",
+                format!("{K3S_CONFIG_FOLDER}/{K3S_KUBECONFIG_FILE_NAME}").as_str(),
+            ],
+        )
+        .await?;
+        String::from_utf8(kubeconfig).map_err(|e| Error::RuntimeConfig(format!("invalid kubeconfig content: {e}")))
+    }
+
     pub async fn get_client(container: &ContainerAsync<K3s>) -> Result<kube::Client> {
+        let client_config = K3s::get_config(container).await?;
+        Ok(kube::Client::try_from(client_config)?)
+    }
+
+    /// Returns client config with the host-mapped API server address.
+    pub async fn get_config(container: &ContainerAsync<K3s>) -> Result<Config> {
         init_crypto_provider();
 
-        let conf_yaml = container.image().get_kubeconfig().await?;
-        let mut config = Kubeconfig::from_yaml(&conf_yaml).expect("Error loading kube config");
+        let config = K3s::get_host_kubeconfig(container).await?;
+        Ok(Config::from_custom_kubeconfig(config, &KubeConfigOptions::default()).await?)
+    }
+
+    /// Returns kubeconfig with the host-mapped API server address, IPv6 loopback is used for IPv6-only clusters.
+    pub async fn get_host_kubeconfig(container: &ContainerAsync<K3s>) -> Result<Kubeconfig> {
+        let conf_yaml = K3s::read_kubeconfig(container).await?;
+        let mut config = Kubeconfig::from_yaml(&conf_yaml)?;
 
-        let port = container.get_host_port_ipv4(K3S_KUBE_API_PORT).await?;
+        let server = if container.image().server_config.ip_family.is_ipv6_only() {
+            let port = container.get_host_port_ipv6(K3S_KUBE_API_PORT).await?;
+            format!("https://[::1]:{port}")
+        } else {
+            let port = container.get_host_port_ipv4(K3S_KUBE_API_PORT).await?;
+            format!("https://127.0.0.1:{port}")
+        };
         config.clusters.iter_mut().for_each(|cluster| {
-            if let Some(server) = cluster.cluster.as_mut().and_then(|c| c.server.as_mut()) {
-                *server = format!("https://127.0.0.1:{}", port)
+            if let Some(s) = cluster.cluster.as_mut().and_then(|c| c.server.as_mut()) {
+                *s = server.clone()
             }
         });
 
-        let client_config = Config::from_custom_kubeconfig(config, &KubeConfigOptions::default()).await?;
-
-        Ok(kube::Client::try_from(client_config)?)
+        Ok(config)
     }
 }
 
+/// Runs future to completion on a new thread with its own runtime, used by guards which clean up on drop:
+/// their clients are bound to the test runtime which may be blocked by the drop.
+pub(crate) fn block_on_new_runtime<F>(future: F) -> Option<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    thread::spawn(move || {
+        runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    })
+    .join()
+    .ok()
+}
+
 pub(crate) async fn run_k3s_cluster() -> Result<ContainerAsync<K3s>> {
     let container = K3s::default()
         .with_all_features(false)
@@ -316,9 +562,11 @@ pub(crate) async fn run_k3s_cluster() -> Result<ContainerAsync<K3s>> {
         .with_userns_mode("host")
         .with_privileged(true)
         .with_mapped_port(K3S_KUBECONFIG_PORT, K3S_KUBE_API_PORT)
+        .with_host(K3S_HOST_GATEWAY_ALIAS, Host::HostGateway)
         .with_network(DOCKER_NETWORK_NAME)
         .start()
         .await?;
+    K3s::wait_ready(&container).await?;
 
     Ok(container)
 }
@@ -360,4 +608,40 @@ mod tests {
         assert!(matches!(version_to_tag("1.10"), Err(Error::RuntimeConfig(_))));
         assert!(matches!(version_to_tag("-"), Err(Error::RuntimeConfig(_))));
     }
+
+    #[test]
+    fn version_to_tag_full_tags_and_requirements() {
+        assert_eq!(version_to_tag("v1.30.6-k3s2").unwrap(), "v1.30.6-k3s2");
+        assert_eq!(version_to_tag("1.32.0-rc1-k3s1").unwrap(), "v1.32.0-rc1-k3s1");
+        assert_eq!(version_to_tag("1.29.9").unwrap(), "v1.29.9-k3s1");
+        assert_eq!(version_to_tag("<1.28").unwrap(), "v1.27.16-k3s1");
+        assert_eq!(version_to_tag(">=1.27, <1.29").unwrap(), "v1.28.14-k3s1");
+        assert!(matches!(version_to_tag("1.29.1"), Err(Error::RuntimeConfig(_))));
+        assert!(matches!(version_to_tag(">=2.0"), Err(Error::RuntimeConfig(_))));
+    }
+
+    #[test]
+    fn version_to_tag_extended_table() {
+        let added = [
+            "v1.25.16-k3s4",
+            "v1.25.16-k3s3",
+            "v1.25.17-rc1-k3s1",
+            "v1.31.3-k3s1",
+            "v1.32.0-k3s1",
+        ]
+        .into_iter()
+        .map(K3sImageTag::parse)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+        let tables = [builtin_k3s_image_tags(), &added];
+
+        assert_eq!(resolve_tag("1.25", &tables).unwrap(), "v1.25.16-k3s4");
+        assert_eq!(resolve_tag("~1.25.0", &tables).unwrap(), "v1.25.16-k3s4");
+        assert_eq!(resolve_tag("1.31.3", &tables).unwrap(), "v1.31.3-k3s1");
+        assert_eq!(resolve_tag("1.31", &tables).unwrap(), "v1.31.1-k3s1");
+        assert_eq!(resolve_tag("", &tables).unwrap(), "v1.31.1-k3s1");
+        assert_eq!(resolve_tag(">=1.29", &tables).unwrap(), "v1.32.0-k3s1");
+        assert_eq!(resolve_tag("~1.31", &tables).unwrap(), "v1.31.3-k3s1");
+        assert!(matches!(add_k3s_image_tags(["1.25"]), Err(Error::RuntimeConfig(_))));
+    }
 }
@@ -0,0 +1,199 @@
+use super::{exec_cmd, K3s};
+use crate::{Error, Result};
+use k8s_openapi::{
+    api::authentication::v1::UserInfo,
+    chrono::{DateTime, Utc},
+};
+use serde::Deserialize;
+use testcontainers::ContainerAsync;
+
+/// API server audit log file inside the container.
+pub const K3S_AUDIT_LOG_FILE: &str = "/var/lib/rancher/k3s/server/logs/audit.log";
+
+pub(crate) const K3S_AUDIT_POLICY_FILE: &str = "/etc/rancher/k3s/audit-policy.yaml";
+
+/// Event of the `audit.k8s.io/v1` API server audit log.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct K3sAuditEvent {
+    pub level: String,
+    #[serde(rename = "auditID")]
+    pub audit_id: String,
+    pub stage: String,
+    #[serde(rename = "requestURI")]
+    pub request_uri: String,
+    pub verb: String,
+    pub user: UserInfo,
+    pub object_ref: Option<K3sAuditObjectRef>,
+    pub response_status: Option<K3sAuditResponseStatus>,
+    pub request_received_timestamp: DateTime<Utc>,
+    pub stage_timestamp: DateTime<Utc>,
+}
+
+/// Resource which the audited request is addressed to.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct K3sAuditObjectRef {
+    pub resource: Option<String>,
+    pub namespace: Option<String>,
+    pub name: Option<String>,
+    pub api_group: Option<String>,
+    pub api_version: Option<String>,
+    pub subresource: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct K3sAuditResponseStatus {
+    pub code: Option<i32>,
+}
+
+/// Filter of the audit events, all specified conditions should match.
+#[derive(Debug, Clone, Default)]
+pub struct K3sAuditFilter {
+    user: Option<String>,
+    verb: Option<String>,
+    resource: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl K3sAuditFilter {
+    /// User name, like `system:serviceaccount:default:operator`.
+    pub fn with_user(self, user: impl Into<String>) -> Self {
+        Self {
+            user: Some(user.into()),
+            ..self
+        }
+    }
+
+    /// Request verb, like `list`, `watch` or `create`.
+    pub fn with_verb(self, verb: impl Into<String>) -> Self {
+        Self {
+            verb: Some(verb.into()),
+            ..self
+        }
+    }
+
+    /// Plural resource name, like `secrets`.
+    pub fn with_resource(self, resource: impl Into<String>) -> Self {
+        Self {
+            resource: Some(resource.into()),
+            ..self
+        }
+    }
+
+    /// Requests received since the specified time (inclusive).
+    pub fn with_since(self, since: DateTime<Utc>) -> Self {
+        Self {
+            since: Some(since),
+            ..self
+        }
+    }
+
+    /// Requests received before the specified time (exclusive).
+    pub fn with_until(self, until: DateTime<Utc>) -> Self {
+        Self {
+            until: Some(until),
+            ..self
+        }
+    }
+
+    pub fn matches(&self, event: &K3sAuditEvent) -> bool {
+        let resource = event.object_ref.as_ref().and_then(|r| r.resource.as_deref());
+
+        self.user
+            .as_ref()
+            .map_or(true, |u| event.user.username.as_ref() == Some(u))
+            && self.verb.as_ref().map_or(true, |v| event.verb == *v)
+            && self.resource.as_deref().map_or(true, |r| resource == Some(r))
+            && self.since.map_or(true, |t| event.request_received_timestamp >= t)
+            && self.until.map_or(true, |t| event.request_received_timestamp < t)
+    }
+}
+
+impl K3s {
+    /// Enables API server audit logging with the specified `audit.k8s.io/v1` policy, see [`K3s::audit_events`].
+    pub fn with_audit_policy(self, policy_yaml: impl Into<String>) -> Self {
+        let mut server_config = self.server_config.clone();
+        server_config.audit_policy = Some(policy_yaml.into());
+        self.with_server_config(server_config)
+    }
+
+    /// Reads and parses audit log, returns events which match the filter.
+    pub async fn audit_events(container: &ContainerAsync<K3s>, filter: &K3sAuditFilter) -> Result<Vec<K3sAuditEvent>> {
+        if container.image().server_config.audit_policy.is_none() {
+            return Err(Error::RuntimeConfig("audit policy isn't configured".into()));
+        }
+
+        // Log file is created on the first audited request
+        let read_log = format!("cat {K3S_AUDIT_LOG_FILE} 2>/dev/null || true");
+        let log = exec_cmd(container, ["sh", "-c", read_log.as_str()]).await?;
+
+        parse_audit_events(&String::from_utf8_lossy(&log), filter)
+    }
+}
+
+/// Parses complete lines of the log, the last line without newline may be still written by the API server,
+/// so it's skipped if it isn't valid.
+fn parse_audit_events(log: &str, filter: &K3sAuditFilter) -> Result<Vec<K3sAuditEvent>> {
+    let (complete, unterminated) = match log.rsplit_once('\n') {
+        Some((complete, last)) => (complete, last),
+        None => ("", log),
+    };
+
+    let mut events = vec![];
+    for line in complete.lines().filter(|l| !l.trim().is_empty()) {
+        let event =
+            serde_json::from_str(line).map_err(|e| Error::RuntimeConfig(format!("invalid audit event: {e}")))?;
+        if filter.matches(&event) {
+            events.push(event);
+        }
+    }
+    if let Ok(event) = serde_json::from_str(unterminated) {
+        if filter.matches(&event) {
+            events.push(event);
+        }
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"
+{"kind":"Event","apiVersion":"audit.k8s.io/v1","level":"Metadata","auditID":"1","stage":"ResponseComplete","requestURI":"/api/v1/secrets","verb":"list","user":{"username":"system:serviceaccount:default:operator","groups":["system:serviceaccounts"]},"objectRef":{"resource":"secrets","apiVersion":"v1"},"responseStatus":{"metadata":{},"code":200},"requestReceivedTimestamp":"2024-10-01T10:00:00.000000Z","stageTimestamp":"2024-10-01T10:00:00.100000Z"}
+{"kind":"Event","apiVersion":"audit.k8s.io/v1","level":"Metadata","auditID":"2","stage":"ResponseComplete","requestURI":"/api/v1/namespaces/default/configmaps","verb":"create","user":{"username":"system:serviceaccount:default:operator"},"objectRef":{"resource":"configmaps","namespace":"default","name":"cm","apiVersion":"v1"},"responseStatus":{"metadata":{},"code":201},"requestReceivedTimestamp":"2024-10-01T10:00:05.000000Z","stageTimestamp":"2024-10-01T10:00:05.100000Z"}
+{"kind":"Event","apiVersion":"audit.k8s.io/v1","level":"Metadata","auditID":"3","stage":"ResponseComplete","requestURI":"/healthz","verb":"get","user":{"username":"system:anonymous"},"requestReceivedTimestamp":"2024-10-01T10:00:10.000000Z","stageTimestamp":"2024-10-01T10:00:10.000000Z"}
+"#;
+
+    #[test]
+    fn filter_audit_events() {
+        let all = parse_audit_events(LOG, &K3sAuditFilter::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[1].object_ref.as_ref().unwrap().name.as_deref(), Some("cm"));
+
+        let operator = K3sAuditFilter::default().with_user("system:serviceaccount:default:operator");
+        assert_eq!(parse_audit_events(LOG, &operator).unwrap().len(), 2);
+
+        let secrets = operator.clone().with_resource("secrets").with_verb("list");
+        let events = parse_audit_events(LOG, &secrets).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].audit_id, "1");
+
+        let window = K3sAuditFilter::default()
+            .with_since("2024-10-01T10:00:05Z".parse().unwrap())
+            .with_until("2024-10-01T10:00:10Z".parse().unwrap());
+        let events = parse_audit_events(LOG, &window).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].verb, "create");
+
+        let any = K3sAuditFilter::default();
+        let partial = format!("{}{{\"kind\":\"Event\",\"apiVer", LOG.trim_start());
+        assert_eq!(parse_audit_events(&partial, &any).unwrap().len(), 3);
+        assert_eq!(parse_audit_events(LOG.trim(), &any).unwrap().len(), 3);
+        assert!(parse_audit_events("{\n", &any).is_err());
+        assert!(parse_audit_events(&format!("{{\n{}", LOG.trim()), &any).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use super::{exec_cmd, K3s};
+use crate::{init_crypto_provider, Error, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use k8s_openapi::api::{
+    authentication::v1::{TokenRequest, TokenRequestSpec},
+    core::v1::ServiceAccount,
+};
+use kube::{
+    api::PostParams,
+    config::{AuthInfo, KubeConfigOptions, Kubeconfig, NamedAuthInfo},
+    Api, Client, Config,
+};
+use rcgen::{CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, KeyPair};
+use secrecy::SecretString;
+use std::time::Duration;
+use testcontainers::ContainerAsync;
+
+/// Folder with k3s server certificates and keys inside the container.
+pub const K3S_SERVER_TLS_FOLDER: &str = "/var/lib/rancher/k3s/server/tls";
+
+const CLIENT_CA_CERT_FILE_NAME: &str = "client-ca.crt";
+const CLIENT_CA_KEY_FILE_NAME: &str = "client-ca.key";
+
+/// The shortest token lifetime which API server accepts.
+pub const MIN_SERVICE_ACCOUNT_TOKEN_TTL: Duration = Duration::from_secs(600);
+
+/// Requests token of the service account and returns client which authenticates with it, endpoint and CA
+/// are taken from the admin config (like one returned by [`super::K3s::get_config`]).
+///
+/// Service account namespace becomes default namespace of the client.
+pub async fn client_for_service_account(config: &Config, namespace: &str, name: &str, ttl: Duration) -> Result<Client> {
+    if ttl < MIN_SERVICE_ACCOUNT_TOKEN_TTL {
+        return Err(Error::RuntimeConfig(format!(
+            "service account token TTL should be at least {MIN_SERVICE_ACCOUNT_TOKEN_TTL:?}"
+        )));
+    }
+
+    let accounts: Api<ServiceAccount> = Api::namespaced(Client::try_from(config.clone())?, namespace);
+    let request = TokenRequest {
+        spec: TokenRequestSpec {
+            expiration_seconds: Some(ttl.as_secs() as i64),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let request = serde_json::to_vec(&request).unwrap();
+    let response: TokenRequest = accounts
+        .create_subresource("token", name, &PostParams::default(), request)
+        .await?;
+    let token = response
+        .status
+        .ok_or_else(|| Error::RuntimeConfig(format!("token of service account `{namespace}/{name}` isn't issued")))?
+        .token;
+
+    let config = token_config(config, namespace, token);
+    Ok(Client::try_from(config)?)
+}
+
+/// Returns client which authenticates with the OIDC ID token (see [`K3s::with_oidc_issuer`]),
+/// endpoint, CA and default namespace are taken from the admin config.
+pub fn client_for_id_token(config: &Config, id_token: impl Into<String>) -> Result<Client> {
+    let config = token_config(config, &config.default_namespace, id_token.into());
+    Ok(Client::try_from(config)?)
+}
+
+impl K3s {
+    /// Issues client certificate for the user (common name) and optional group (organization) signed by k3s
+    /// client CA, returns client which authenticates with it and kubeconfig with the host-mapped API server address.
+    pub async fn client_for_user(
+        container: &ContainerAsync<K3s>,
+        user: impl AsRef<str>,
+        group: Option<&str>,
+    ) -> Result<(Client, Kubeconfig)> {
+        let user = user.as_ref();
+
+        let read = |file: &str| {
+            let path = format!("{K3S_SERVER_TLS_FOLDER}/{file}");
+            async move {
+                let content = exec_cmd(container, ["cat", path.as_str()]).await?;
+                String::from_utf8(content).map_err(|e| Error::RuntimeConfig(format!("invalid `{path}` content: {e}")))
+            }
+        };
+        let ca_cert = read(CLIENT_CA_CERT_FILE_NAME).await?;
+        let ca_key = read(CLIENT_CA_KEY_FILE_NAME).await?;
+        let (cert, key) = issue_client_cert(&ca_cert, &ca_key, user, group)?;
+
+        let kubeconfig = user_kubeconfig(K3s::get_host_kubeconfig(container).await?, user, &cert, &key);
+        init_crypto_provider();
+        let config = Config::from_custom_kubeconfig(kubeconfig.clone(), &KubeConfigOptions::default()).await?;
+
+        Ok((Client::try_from(config)?, kubeconfig))
+    }
+}
+
+/// Returns PEM-encoded certificate and key of the user signed by the CA.
+fn issue_client_cert(ca_cert: &str, ca_key: &str, user: &str, group: Option<&str>) -> Result<(String, String)> {
+    let error = |e: rcgen::Error| Error::RuntimeConfig(format!("can't issue client certificate: {e}"));
+
+    let ca_key = KeyPair::from_pem(ca_key).map_err(error)?;
+    let ca_cert = CertificateParams::from_ca_cert_pem(ca_cert)
+        .and_then(|params| params.self_signed(&ca_key))
+        .map_err(error)?;
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, user);
+    if let Some(group) = group {
+        params.distinguished_name.push(DnType::OrganizationName, group);
+    }
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+
+    let key = KeyPair::generate().map_err(error)?;
+    let cert = params.signed_by(&key, &ca_cert, &ca_key).map_err(error)?;
+
+    Ok((cert.pem(), key.serialize_pem()))
+}
+
+/// Replaces credentials of the k3s kubeconfig with the user certificate.
+fn user_kubeconfig(mut kubeconfig: Kubeconfig, user: &str, cert: &str, key: &str) -> Kubeconfig {
+    kubeconfig.auth_infos = vec![NamedAuthInfo {
+        name: user.to_string(),
+        auth_info: Some(AuthInfo {
+            client_certificate_data: Some(BASE64.encode(cert)),
+            client_key_data: Some(SecretString::from(BASE64.encode(key))),
+            ..Default::default()
+        }),
+    }];
+    kubeconfig
+        .contexts
+        .iter_mut()
+        .filter_map(|c| c.context.as_mut())
+        .for_each(|c| c.user = user.to_string());
+
+    kubeconfig
+}
+
+/// Copy of the config which authenticates with bearer token only.
+fn token_config(config: &Config, namespace: &str, token: String) -> Config {
+    Config {
+        default_namespace: namespace.to_string(),
+        auth_info: AuthInfo {
+            token: Some(SecretString::from(token)),
+            ..Default::default()
+        },
+        ..config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, DnValue, IsCa};
+
+    #[test]
+    fn issue_user_client_cert() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca = CertificateParams::new(vec![]).unwrap();
+        ca.distinguished_name.push(DnType::CommonName, "k3s-client-ca");
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca.self_signed(&ca_key).unwrap();
+
+        let (cert, key) = issue_client_cert(&ca.pem(), &ca_key.serialize_pem(), "alice", Some("developers")).unwrap();
+        let params = CertificateParams::from_ca_cert_pem(&cert).unwrap();
+        assert_eq!(
+            params.distinguished_name.get(&DnType::CommonName),
+            Some(&DnValue::Utf8String("alice".to_string()))
+        );
+        assert_eq!(
+            params.distinguished_name.get(&DnType::OrganizationName),
+            Some(&DnValue::Utf8String("developers".to_string()))
+        );
+        assert!(key.contains("PRIVATE KEY"));
+
+        let (cert, _) = issue_client_cert(&ca.pem(), &ca_key.serialize_pem(), "bob", None).unwrap();
+        let params = CertificateParams::from_ca_cert_pem(&cert).unwrap();
+        assert!(params.distinguished_name.get(&DnType::OrganizationName).is_none());
+    }
+
+    #[test]
+    fn service_account_token_config() {
+        let mut admin = Config::new("https://127.0.0.1:6443".parse().unwrap());
+        admin.root_cert = Some(vec![b"CA".to_vec()]);
+        admin.auth_info.client_certificate_data = Some("CERT".to_string());
+
+        let config = token_config(&admin, "apps", "TOKEN".to_string());
+        assert_eq!(config.cluster_url, admin.cluster_url);
+        assert_eq!(config.root_cert, admin.root_cert);
+        assert_eq!(config.default_namespace, "apps");
+        assert!(config.auth_info.token.is_some());
+        assert!(config.auth_info.client_certificate_data.is_none());
+    }
+}
@@ -0,0 +1,318 @@
+use super::{
+    images::import_images, network::create_docker_ipv6_network, K3s, K3sIpFamily, DOCKER_IPV6_NETWORK_NAME,
+    DOCKER_IPV6_NETWORK_SUBNET, K3S_HOST_GATEWAY_ALIAS, K3S_IMAGE_NAME, K3S_KUBE_API_PORT,
+};
+use crate::{Result, DOCKER_NETWORK_NAME};
+use rand::{distributions::Alphanumeric, Rng};
+use std::{borrow::Cow, path::Path};
+use testcontainers::{
+    core::{Host, WaitFor},
+    runners::AsyncRunner as _,
+    ContainerAsync, CopyToContainer, Image, ImageExt as _,
+};
+
+pub const K3S_CLUSTER_DEFAULT_AGENTS: usize = 2;
+
+const CLUSTER_TOKEN_LENGTH: usize = 32;
+const CLUSTER_NAME_SUFFIX_LENGTH: usize = 8;
+
+/// Worker node of the multi-node cluster, runs `k3s agent` and joins the server using the cluster token.
+#[derive(Debug, Clone)]
+pub struct K3sAgent {
+    tag: String,
+    server_url: String,
+    token: String,
+    snapshotter: String,
+    args: Vec<String>,
+    registries_files: Vec<CopyToContainer>,
+    image_archives: Vec<CopyToContainer>,
+}
+
+impl Image for K3sAgent {
+    fn name(&self) -> &str {
+        K3S_IMAGE_NAME
+    }
+
+    fn tag(&self) -> &str {
+        self.tag.as_str()
+    }
+
+    fn ready_conditions(&self) -> Vec<WaitFor> {
+        vec![WaitFor::message_on_stderr("Successfully registered node")]
+    }
+
+    fn env_vars(&self) -> impl IntoIterator<Item = (impl Into<Cow<'_, str>>, impl Into<Cow<'_, str>>)> {
+        vec![
+            (String::from("K3S_URL"), self.server_url.clone()),
+            (String::from("K3S_TOKEN"), self.token.clone()),
+        ]
+    }
+
+    fn cmd(&self) -> impl IntoIterator<Item = impl Into<Cow<'_, str>>> {
+        let mut cmd = vec![String::from("agent"), format!("--snapshotter={}", self.snapshotter)];
+        cmd.extend(self.args.iter().cloned());
+        cmd
+    }
+
+    fn copy_to_sources(&self) -> impl IntoIterator<Item = &CopyToContainer> {
+        self.registries_files.iter().chain(self.image_archives.iter())
+    }
+}
+
+/// Builder of the multi-node k3s cluster: single server container plus a number of agent containers.
+#[derive(Debug, Clone)]
+pub struct K3sCluster {
+    server: K3s,
+    agents: usize,
+    name: String,
+    network: Option<String>,
+    token: String,
+}
+
+impl Default for K3sCluster {
+    fn default() -> Self {
+        Self {
+            server: K3s::default(),
+            agents: K3S_CLUSTER_DEFAULT_AGENTS,
+            name: format!("k3s-{}", random_string(CLUSTER_NAME_SUFFIX_LENGTH).to_lowercase()),
+            network: None,
+            token: random_string(CLUSTER_TOKEN_LENGTH),
+        }
+    }
+}
+
+impl K3sCluster {
+    pub fn with_server(self, server: K3s) -> Self {
+        Self { server, ..self }
+    }
+
+    pub fn with_agents(self, agents: usize) -> Self {
+        Self { agents, ..self }
+    }
+
+    pub fn with_name(self, name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..self
+        }
+    }
+
+    /// Docker network of all nodes, it should have IPv6 enabled for dual-stack and IPv6-only clusters.
+    pub fn with_network(self, network: impl Into<String>) -> Self {
+        Self {
+            network: Some(network.into()),
+            ..self
+        }
+    }
+
+    /// Network which nodes are attached to: explicitly set one, [`DOCKER_IPV6_NETWORK_NAME`] if server
+    /// IP family isn't IPv4-only (it's created on start) or [`DOCKER_NETWORK_NAME`] otherwise.
+    pub fn network(&self) -> &str {
+        match &self.network {
+            Some(network) => network,
+            None if self.server.server_config.ip_family != K3sIpFamily::Ipv4 => DOCKER_IPV6_NETWORK_NAME,
+            None => DOCKER_NETWORK_NAME,
+        }
+    }
+
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            ..self
+        }
+    }
+
+    pub fn server_name(&self) -> String {
+        format!("{}-server", self.name)
+    }
+
+    pub fn agent_name(&self, index: usize) -> String {
+        format!("{}-agent-{index}", self.name)
+    }
+
+    pub fn server_url(&self) -> String {
+        format!("https://{}:{}", self.server_name(), K3S_KUBE_API_PORT.as_u16())
+    }
+
+    /// Agent image which joins the server of this cluster.
+    fn agent(&self) -> K3sAgent {
+        K3sAgent {
+            tag: self.server.tag.clone(),
+            server_url: self.server_url(),
+            token: self.token.clone(),
+            snapshotter: self.server.features.snapshotter.clone(),
+            args: self.server.server_config.agent_args(),
+            registries_files: self.server.registries_files.clone(),
+            image_archives: self.server.image_archives.clone(),
+        }
+    }
+
+    /// Server image which doesn't wait for startup manifests, it's done by [`K3sCluster::start`] once agents have joined.
+    fn server(&self) -> K3s {
+        K3s {
+            defer_manifests_wait: true,
+            ..self.server.clone()
+        }
+    }
+
+    /// Starts the server first, joins all agents to it and waits until the cluster is ready.
+    /// Startup manifests are waited for after agents have joined, as their pods may need agent nodes.
+    pub async fn start(self) -> Result<K3sClusterHandle> {
+        let agent = self.agent();
+        let network = self.network().to_string();
+        if network == DOCKER_IPV6_NETWORK_NAME {
+            create_docker_ipv6_network(DOCKER_IPV6_NETWORK_NAME, DOCKER_IPV6_NETWORK_SUBNET).await?;
+        }
+
+        let server = self
+            .server()
+            .with_token(self.token.clone())
+            .with_container_name(self.server_name())
+            .with_userns_mode("host")
+            .with_privileged(true)
+            .with_host(K3S_HOST_GATEWAY_ALIAS, Host::HostGateway)
+            .with_network(network.clone())
+            .start()
+            .await?;
+
+        let mut agents = Vec::with_capacity(self.agents);
+        for index in 0..self.agents {
+            let container = agent
+                .clone()
+                .with_container_name(self.agent_name(index))
+                .with_userns_mode("host")
+                .with_privileged(true)
+                .with_network(network.clone())
+                .start()
+                .await?;
+            agents.push(container);
+        }
+
+        if let Some(cmd) = self.server.wait_for_manifests_cmd() {
+            server.exec(cmd).await?;
+        }
+
+        let nodes = self.agents + usize::from(self.server.features.agent);
+        K3s::wait_ready_with_nodes(&server, nodes).await?;
+
+        Ok(K3sClusterHandle { server, agents })
+    }
+}
+
+/// Running multi-node cluster, owns all node containers.
+pub struct K3sClusterHandle {
+    server: ContainerAsync<K3s>,
+    agents: Vec<ContainerAsync<K3sAgent>>,
+}
+
+impl K3sClusterHandle {
+    pub fn server(&self) -> &ContainerAsync<K3s> {
+        &self.server
+    }
+
+    pub fn agents(&self) -> &[ContainerAsync<K3sAgent>] {
+        &self.agents
+    }
+
+    pub fn agent(&self, index: usize) -> Option<&ContainerAsync<K3sAgent>> {
+        self.agents.get(index)
+    }
+
+    pub async fn get_client(&self) -> Result<kube::Client> {
+        K3s::get_client(&self.server).await
+    }
+
+    /// Imports image archives into containerd of every node, server is skipped if it runs without agent.
+    pub async fn load_images(&self, archives: impl IntoIterator<Item = impl AsRef<Path>>) -> Result<()> {
+        for archive in archives {
+            if self.server.image().features.agent {
+                import_images(&self.server, tokio::fs::File::open(archive.as_ref()).await?).await?;
+            }
+            for agent in &self.agents {
+                import_images(agent, tokio::fs::File::open(archive.as_ref()).await?).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agent_requests() {
+        let cluster = K3sCluster::default()
+            .with_name("test")
+            .with_token("secret")
+            .with_network("k3s-net")
+            .with_server(
+                K3s::default()
+                    .with_kube_version("1.30")
+                    .with_snapshotter("overlayfs")
+                    .with_feature_gate("SidecarContainers", true)
+                    .with_image_archive("/tmp/images.tar"),
+            );
+
+        assert_eq!(cluster.server_name(), "test-server");
+        assert_eq!(cluster.agent_name(1), "test-agent-1");
+
+        let agent = cluster.agent();
+        assert_eq!(agent.tag(), cluster.server.tag());
+        let env = agent
+            .env_vars()
+            .into_iter()
+            .map(|(k, v)| (k.into().to_string(), v.into().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            env,
+            vec![
+                ("K3S_URL".to_string(), "https://test-server:6443".to_string()),
+                ("K3S_TOKEN".to_string(), "secret".to_string()),
+            ]
+        );
+        let cmd = agent
+            .cmd()
+            .into_iter()
+            .map(|c| c.into().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cmd,
+            vec![
+                "agent",
+                "--snapshotter=overlayfs",
+                "--kubelet-arg=feature-gates=SidecarContainers=true",
+                "--kube-proxy-arg=feature-gates=SidecarContainers=true",
+            ]
+        );
+
+        let copy_to = format!("{:?}", agent.copy_to_sources().into_iter().collect::<Vec<_>>());
+        assert!(copy_to.contains("/000-images.tar"), "{copy_to}");
+
+        let request = agent
+            .with_container_name(cluster.agent_name(0))
+            .with_network(cluster.network());
+        assert_eq!(request.container_name().as_deref(), Some("test-agent-0"));
+        assert_eq!(request.network().as_deref(), Some("k3s-net"));
+
+        let server = cluster.server();
+        assert!(server.defer_manifests_wait);
+        assert!(!cluster.server.defer_manifests_wait);
+
+        let other = K3sCluster::default();
+        assert_ne!(other.token, K3sCluster::default().token);
+        assert!(other.server_name().starts_with("k3s-"));
+        assert_eq!(other.network(), DOCKER_NETWORK_NAME);
+        let other = other.with_server(K3s::default().with_ip_family(K3sIpFamily::DualStack));
+        assert_eq!(other.network(), DOCKER_IPV6_NETWORK_NAME);
+    }
+}
@@ -0,0 +1,140 @@
+use super::{manifests::ManifestObject, K3s};
+use crate::{Error, Result};
+use std::{fmt::Display, str::FromStr};
+
+/// Flannel backend of the cluster network, `None` disables flannel to use custom CNI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum K3sFlannelBackend {
+    Vxlan,
+    HostGw,
+    /// Requires `wireguard` kernel module on the Docker host.
+    WireguardNative,
+    None,
+}
+
+impl K3sFlannelBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            K3sFlannelBackend::Vxlan => "vxlan",
+            K3sFlannelBackend::HostGw => "host-gw",
+            K3sFlannelBackend::WireguardNative => "wireguard-native",
+            K3sFlannelBackend::None => "none",
+        }
+    }
+}
+
+impl Display for K3sFlannelBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for K3sFlannelBackend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "vxlan" => Ok(K3sFlannelBackend::Vxlan),
+            "host-gw" => Ok(K3sFlannelBackend::HostGw),
+            "wireguard-native" => Ok(K3sFlannelBackend::WireguardNative),
+            "none" => Ok(K3sFlannelBackend::None),
+            _ => Err(Error::RuntimeConfig(format!("unsupported flannel backend `{s}`"))),
+        }
+    }
+}
+
+impl K3s {
+    /// Sets flannel backend, `None` also disables embedded network policy controller,
+    /// so CNI should be installed using [`K3s::with_cni_manifest`].
+    pub fn with_flannel_backend(mut self, backend: K3sFlannelBackend) -> Self {
+        self.set_flannel_backend(backend);
+        let features = self.features.clone();
+        self.with_features(features)
+    }
+
+    /// Network policy controller relies on flannel, so it's disabled together with it,
+    /// config files should be re-rendered after that.
+    pub(super) fn set_flannel_backend(&mut self, backend: K3sFlannelBackend) {
+        self.server_config.flannel_backend = Some(backend);
+        if backend == K3sFlannelBackend::None {
+            self.features.network_policy = false;
+        }
+    }
+
+    /// Adds CNI manifest, panics on invalid manifest, see [`K3s::try_with_cni_manifest`].
+    pub fn with_cni_manifest(self, name: impl Into<String>, yaml: impl Into<String>) -> Self {
+        self.try_with_cni_manifest(name, yaml).unwrap()
+    }
+
+    /// Adds CNI manifest which is applied at startup, readiness check waits until all its DaemonSets
+    /// are rolled out before checking nodes, see [`super::K3sReadinessStage::CniReady`].
+    pub fn try_with_cni_manifest(self, name: impl Into<String>, yaml: impl Into<String>) -> Result<Self> {
+        let yaml = yaml.into();
+        let objects = ManifestObject::from_yaml(&yaml)?;
+        let mut cni_daemon_sets = self.cni_daemon_sets.clone();
+        cni_daemon_sets.extend(
+            objects
+                .iter()
+                .filter(|object| object.kind() == "DaemonSet")
+                .map(|object| {
+                    let namespace = object.namespace().unwrap_or("default").to_string();
+                    (namespace, object.name().to_string())
+                }),
+        );
+
+        Self {
+            cni_daemon_sets,
+            ..self
+        }
+        .with_manifest_objects(name, yaml, objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CNI: &str = r#"
+apiVersion: v1
+kind: ServiceAccount
+metadata:
+  name: cni
+  namespace: kube-system
+---
+apiVersion: apps/v1
+kind: DaemonSet
+metadata:
+  name: cni-node
+  namespace: kube-system
+spec: {}
+"#;
+
+    #[test]
+    fn flannel_backend_and_cni() {
+        let k3s = K3s::default()
+            .with_flannel_backend(K3sFlannelBackend::None)
+            .with_cni_manifest("cni", CNI);
+        assert!(!k3s.features.network_policy);
+        assert_eq!(
+            k3s.cni_daemon_sets,
+            vec![("kube-system".to_string(), "cni-node".to_string())]
+        );
+        let config = k3s.server_config.render(&k3s.features);
+        assert_eq!(config["flannel-backend"], "none");
+
+        let k3s = K3s::default().with_server_arg("--flannel-backend=host-gw");
+        assert_eq!(k3s.server_config.flannel_backend, Some(K3sFlannelBackend::HostGw));
+        assert!(k3s.features.network_policy);
+        assert!(K3s::default().try_with_server_arg("--flannel-backend=ipsec").is_err());
+
+        let k3s = K3s::default().with_server_arg("--flannel-backend=none");
+        assert!(!k3s.features.network_policy);
+        let config = k3s.server_config.render(&k3s.features);
+        assert_eq!(config["disable-network-policy"], true);
+
+        assert!(matches!(
+            K3s::default().try_with_cni_manifest("cni", "kind: ["),
+            Err(Error::RuntimeConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,563 @@
+use super::{
+    audit::K3S_AUDIT_POLICY_FILE, oidc::K3sOidcIssuer, K3s, K3sFeatures, K3sFlannelBackend, K3sIpFamily,
+    K3S_AUDIT_LOG_FILE, K3S_CONFIG_FOLDER,
+};
+use crate::{Error, Result};
+use serde_yaml::{Mapping, Value};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+use testcontainers::{CopyDataSource, CopyToContainer};
+
+const CONFIG_FILE_NAME: &str = "config.yaml";
+const ADMISSION_CONFIG_FILE_NAME: &str = "admission-config.yaml";
+
+/// Settings which are required by the module itself (kubeconfig location, API port).
+const RESERVED_SETTINGS: [&str; 4] = [
+    "config",
+    "write-kubeconfig",
+    "write-kubeconfig-mode",
+    "https-listen-port",
+];
+
+/// Settings which can be specified multiple times, their values are collected into a list.
+const REPEATABLE_SETTINGS: [&str; 13] = [
+    "tls-san",
+    "disable",
+    "etcd-arg",
+    "kube-apiserver-arg",
+    "kube-controller-manager-arg",
+    "kube-scheduler-arg",
+    "kube-cloud-controller-manager-arg",
+    "kubelet-arg",
+    "kube-proxy-arg",
+    "node-label",
+    "node-taint",
+    "node-ip",
+    "node-external-ip",
+];
+
+/// Settings with arguments of the components which accept `--feature-gates` flag.
+const FEATURE_GATES_COMPONENTS: [&str; 5] = [
+    "kube-apiserver-arg",
+    "kube-controller-manager-arg",
+    "kube-scheduler-arg",
+    "kubelet-arg",
+    "kube-proxy-arg",
+];
+
+/// Pairs of settings which can't be used together.
+const CONFLICTING_SETTINGS: [(&str, &str); 1] = [("cluster-init", "datastore-endpoint")];
+
+/// Pod Security Standards level which is enforced by `PodSecurity` admission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum K3sPodSecurityLevel {
+    Privileged,
+    Baseline,
+    Restricted,
+}
+
+impl K3sPodSecurityLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            K3sPodSecurityLevel::Privileged => "privileged",
+            K3sPodSecurityLevel::Baseline => "baseline",
+            K3sPodSecurityLevel::Restricted => "restricted",
+        }
+    }
+}
+
+impl Display for K3sPodSecurityLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for K3sPodSecurityLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "privileged" => Ok(K3sPodSecurityLevel::Privileged),
+            "baseline" => Ok(K3sPodSecurityLevel::Baseline),
+            "restricted" => Ok(K3sPodSecurityLevel::Restricted),
+            _ => Err(Error::RuntimeConfig(format!("unsupported pod security level `{s}`"))),
+        }
+    }
+}
+
+/// Server settings of `/etc/rancher/k3s/config.yaml` which aren't covered by [`K3sFeatures`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct K3sServerConfig {
+    settings: Mapping,
+    feature_gates: BTreeMap<String, bool>,
+    admission_plugins: BTreeMap<String, bool>,
+    pod_security_level: Option<K3sPodSecurityLevel>,
+    pub(super) audit_policy: Option<String>,
+    pub(super) ip_family: K3sIpFamily,
+    pub(super) flannel_backend: Option<K3sFlannelBackend>,
+    pub(super) oidc_issuer: Option<K3sOidcIssuer>,
+}
+
+impl K3sServerConfig {
+    /// Renders features and settings into the config files to copy into the container.
+    pub(crate) fn files(&self, features: &K3sFeatures) -> Vec<CopyToContainer> {
+        let config = serde_yaml::to_string(&self.render(features)).unwrap();
+        let mut files = vec![CopyToContainer::new(
+            CopyDataSource::Data(config.into_bytes()),
+            format!("{K3S_CONFIG_FOLDER}/{CONFIG_FILE_NAME}"),
+        )];
+        if let Some(level) = &self.pod_security_level {
+            files.push(CopyToContainer::new(
+                CopyDataSource::Data(pod_security_admission_config(*level).into_bytes()),
+                format!("{K3S_CONFIG_FOLDER}/{ADMISSION_CONFIG_FILE_NAME}"),
+            ));
+        }
+        if let Some(policy) = &self.audit_policy {
+            files.push(CopyToContainer::new(
+                CopyDataSource::Data(policy.clone().into_bytes()),
+                K3S_AUDIT_POLICY_FILE,
+            ));
+        }
+        if let Some(issuer) = &self.oidc_issuer {
+            files.extend(issuer.files());
+        }
+
+        files
+    }
+
+    /// Arguments of the agent nodes which should match the server configuration.
+    pub(crate) fn agent_args(&self) -> Vec<String> {
+        match self.feature_gates_arg() {
+            Some(feature_gates) => vec![
+                format!("--kubelet-arg={feature_gates}"),
+                format!("--kube-proxy-arg={feature_gates}"),
+            ],
+            None => vec![],
+        }
+    }
+
+    fn feature_gates_arg(&self) -> Option<String> {
+        if self.feature_gates.is_empty() {
+            return None;
+        }
+
+        let gates = self
+            .feature_gates
+            .iter()
+            .map(|(name, enabled)| format!("{name}={enabled}"))
+            .collect::<Vec<_>>();
+        Some(format!("feature-gates={}", gates.join(",")))
+    }
+
+    fn apiserver_args(&self) -> Vec<String> {
+        let plugins = |enabled: bool| {
+            self.admission_plugins
+                .iter()
+                .filter(|(_, e)| **e == enabled)
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        let mut args = vec![];
+        for (enabled, flag) in [(true, "enable-admission-plugins"), (false, "disable-admission-plugins")] {
+            let plugins = plugins(enabled);
+            if !plugins.is_empty() {
+                args.push(format!("{flag}={}", plugins.join(",")));
+            }
+        }
+        if self.pod_security_level.is_some() {
+            args.push(format!(
+                "admission-control-config-file={K3S_CONFIG_FOLDER}/{ADMISSION_CONFIG_FILE_NAME}"
+            ));
+        }
+        if self.audit_policy.is_some() {
+            args.push(format!("audit-policy-file={K3S_AUDIT_POLICY_FILE}"));
+            args.push(format!("audit-log-path={K3S_AUDIT_LOG_FILE}"));
+        }
+        if let Some(issuer) = &self.oidc_issuer {
+            args.extend(issuer.apiserver_args());
+        }
+
+        args
+    }
+
+    pub(super) fn render(&self, features: &K3sFeatures) -> Mapping {
+        let mut config = Mapping::new();
+        config.insert("snapshotter".into(), features.snapshotter.clone().into());
+
+        let mut disable = [
+            (features.traefik, "traefik"),
+            (features.service_lb, "servicelb"),
+            (features.coredns, "coredns"),
+            (features.local_storage, "local-storage"),
+            (features.metrics_server, "metrics-server"),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| !enabled)
+        .map(|(_, component)| Value::from(component))
+        .collect::<Vec<_>>();
+        if let Some(Value::Sequence(extra)) = self.settings.get("disable") {
+            disable.extend(extra.iter().cloned());
+        }
+        if !disable.is_empty() {
+            config.insert("disable".into(), Value::Sequence(disable));
+        }
+
+        for (enabled, flag) in [
+            (!features.agent, "disable-agent"),
+            (!features.helm_controller, "disable-helm-controller"),
+            (!features.network_policy, "disable-network-policy"),
+            (features.cluster_init, "cluster-init"),
+        ] {
+            if enabled {
+                config.insert(flag.into(), true.into());
+            }
+        }
+
+        for (key, value) in &self.settings {
+            if key.as_str() != Some("disable") {
+                config.insert(key.clone(), value.clone());
+            }
+        }
+
+        if let Some(backend) = self.flannel_backend {
+            config.insert("flannel-backend".into(), backend.as_str().into());
+        }
+        for (key, value) in self.ip_family.settings() {
+            if !config.contains_key(key) {
+                config.insert(key.into(), value);
+            }
+        }
+        if self.ip_family != K3sIpFamily::Ipv4 {
+            append_setting(&mut config, "tls-san", "::1".to_string());
+        }
+
+        for arg in self.apiserver_args() {
+            append_setting(&mut config, "kube-apiserver-arg", arg);
+        }
+        if let Some(feature_gates) = self.feature_gates_arg() {
+            for component in FEATURE_GATES_COMPONENTS {
+                append_setting(&mut config, component, feature_gates.clone());
+            }
+        }
+
+        config
+    }
+
+    fn is_set(&self, key: &str, features: &K3sFeatures) -> bool {
+        match key {
+            "cluster-init" => features.cluster_init,
+            _ => self.settings.contains_key(key),
+        }
+    }
+}
+
+impl K3s {
+    /// Adds k3s server argument (like `--tls-san=example.com` or `--cluster-init`), panics on invalid or
+    /// conflicting settings, see [`K3s::try_with_server_arg`].
+    pub fn with_server_arg(self, arg: impl AsRef<str>) -> Self {
+        self.try_with_server_arg(arg).unwrap()
+    }
+
+    /// Adds k3s server argument, it's rendered into `/etc/rancher/k3s/config.yaml` together with features.
+    pub fn try_with_server_arg(self, arg: impl AsRef<str>) -> Result<Self> {
+        let arg = arg.as_ref();
+        let Some(arg) = arg.strip_prefix("--") else {
+            return Err(Error::RuntimeConfig(format!("invalid k3s server argument `{arg}`")));
+        };
+
+        match arg.split_once('=') {
+            Some((key, value)) => self.try_with_setting(key, value.into()),
+            None => self.try_with_setting(arg, true.into()),
+        }
+    }
+
+    /// Merges k3s config file content (mapping of server settings), panics on invalid or conflicting settings,
+    /// see [`K3s::try_with_config_yaml`].
+    pub fn with_config_yaml(self, yaml: impl AsRef<str>) -> Self {
+        self.try_with_config_yaml(yaml).unwrap()
+    }
+
+    /// Merges k3s config file content, settings which are covered by features (like `disable: [traefik]`)
+    /// update corresponding features.
+    pub fn try_with_config_yaml(self, yaml: impl AsRef<str>) -> Result<Self> {
+        let config: Option<Mapping> = serde_yaml::from_str(yaml.as_ref())
+            .map_err(|e| Error::RuntimeConfig(format!("invalid k3s config: {e}")))?;
+
+        let mut k3s = self;
+        for (key, value) in config.unwrap_or_default() {
+            let Value::String(key) = key else {
+                return Err(Error::RuntimeConfig(format!("invalid k3s config key `{key:?}`")));
+            };
+            k3s = k3s.try_with_setting(&key, value)?;
+        }
+
+        Ok(k3s)
+    }
+
+    fn try_with_setting(mut self, key: &str, value: Value) -> Result<Self> {
+        if RESERVED_SETTINGS.contains(&key) {
+            return Err(Error::RuntimeConfig(format!(
+                "k3s setting `{key}` is managed by the module and can't be changed"
+            )));
+        }
+
+        match key {
+            "snapshotter" => self.features.snapshotter = setting_string(key, &value)?,
+            "token" => self.token = Some(setting_string(key, &value)?),
+            "disable-agent" => self.features.agent = !setting_bool(key, &value)?,
+            "disable-helm-controller" => self.features.helm_controller = !setting_bool(key, &value)?,
+            "disable-network-policy" => self.features.network_policy = !setting_bool(key, &value)?,
+            "cluster-init" => self.features.cluster_init = setting_bool(key, &value)?,
+            "flannel-backend" => self.set_flannel_backend(setting_string(key, &value)?.parse()?),
+            "disable" => {
+                for component in setting_list(key, &value)? {
+                    match component.as_str() {
+                        "traefik" => self.features.traefik = false,
+                        "servicelb" => self.features.service_lb = false,
+                        "coredns" => self.features.coredns = false,
+                        "local-storage" => self.features.local_storage = false,
+                        "metrics-server" => self.features.metrics_server = false,
+                        _ => append_setting(&mut self.server_config.settings, key, component),
+                    }
+                }
+            }
+            _ if REPEATABLE_SETTINGS.contains(&key) => {
+                for item in setting_list(key, &value)? {
+                    append_setting(&mut self.server_config.settings, key, item);
+                }
+            }
+            _ => {
+                let value = Value::String(setting_string(key, &value)?);
+                match self.server_config.settings.get(key) {
+                    Some(existing) if *existing != value => {
+                        return Err(Error::RuntimeConfig(format!(
+                            "conflicting values of k3s setting `{key}`: {existing:?} and {value:?}"
+                        )));
+                    }
+                    _ => {
+                        self.server_config.settings.insert(key.into(), value);
+                    }
+                }
+            }
+        }
+
+        for (a, b) in CONFLICTING_SETTINGS {
+            if self.server_config.is_set(a, &self.features) && self.server_config.is_set(b, &self.features) {
+                return Err(Error::RuntimeConfig(format!(
+                    "k3s settings `{a}` and `{b}` can't be used together"
+                )));
+            }
+        }
+
+        let features = self.features.clone();
+        Ok(self.with_features(features))
+    }
+
+    /// Enables or disables Kubernetes feature gate on the API server, controller manager, scheduler,
+    /// kubelet and kube-proxy.
+    pub fn with_feature_gate(self, name: impl Into<String>, enabled: bool) -> Self {
+        let mut server_config = self.server_config.clone();
+        server_config.feature_gates.insert(name.into(), enabled);
+        self.with_server_config(server_config)
+    }
+
+    /// Enables API server admission plugin (like `NamespaceAutoProvision`) in addition to the default ones.
+    pub fn with_admission_plugin(self, name: impl Into<String>) -> Self {
+        let mut server_config = self.server_config.clone();
+        server_config.admission_plugins.insert(name.into(), true);
+        self.with_server_config(server_config)
+    }
+
+    /// Disables API server admission plugin which is enabled by default (like `NamespaceLifecycle`).
+    pub fn with_disabled_admission_plugin(self, name: impl Into<String>) -> Self {
+        let mut server_config = self.server_config.clone();
+        server_config.admission_plugins.insert(name.into(), false);
+        self.with_server_config(server_config)
+    }
+
+    /// Sets cluster-wide `PodSecurity` admission level which is enforced, audited and warned about,
+    /// `kube-system` namespace is exempted.
+    pub fn with_pod_security_level(self, level: K3sPodSecurityLevel) -> Self {
+        let mut server_config = self.server_config.clone();
+        server_config.pod_security_level = Some(level);
+        self.with_server_config(server_config)
+    }
+
+    /// Updates features and re-renders config files.
+    pub(super) fn with_features(self, features: K3sFeatures) -> Self {
+        Self {
+            config_files: self.server_config.files(&features),
+            features,
+            ..self
+        }
+    }
+
+    pub(super) fn with_server_config(self, server_config: K3sServerConfig) -> Self {
+        Self {
+            config_files: server_config.files(&self.features),
+            server_config,
+            ..self
+        }
+    }
+}
+
+fn pod_security_admission_config(level: K3sPodSecurityLevel) -> String {
+    format!(
+        r#"apiVersion: apiserver.config.k8s.io/v1
+kind: AdmissionConfiguration
+plugins:
+  - name: PodSecurity
+    configuration:
+      apiVersion: pod-security.admission.config.k8s.io/v1
+      kind: PodSecurityConfiguration
+      defaults:
+        enforce: "{level}"
+        enforce-version: latest
+        audit: "{level}"
+        audit-version: latest
+        warn: "{level}"
+        warn-version: latest
+      exemptions:
+        namespaces: [kube-system]
+"#
+    )
+}
+
+fn setting_string(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(Error::RuntimeConfig(format!(
+            "k3s setting `{key}` should be a scalar, got {value:?}"
+        ))),
+    }
+}
+
+fn setting_bool(key: &str, value: &Value) -> Result<bool> {
+    setting_string(key, value)?
+        .parse()
+        .map_err(|_| Error::RuntimeConfig(format!("k3s setting `{key}` should be a boolean, got {value:?}")))
+}
+
+/// Values of the repeatable setting, scalar values may contain comma-separated list like CLI flags.
+fn setting_list(key: &str, value: &Value) -> Result<Vec<String>> {
+    match value {
+        Value::Sequence(items) => items.iter().map(|item| setting_string(key, item)).collect(),
+        _ if key == "disable" => Ok(setting_string(key, value)?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .collect()),
+        _ => Ok(vec![setting_string(key, value)?]),
+    }
+}
+
+fn append_setting(settings: &mut Mapping, key: &str, item: String) {
+    let list = settings.entry(key.into()).or_insert_with(|| Value::Sequence(vec![]));
+    if let Value::Sequence(list) = list {
+        let item = Value::String(item);
+        if !list.contains(&item) {
+            list.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_server_config() {
+        let k3s = K3s::default()
+            .with_traefik(false)
+            .with_server_arg("--tls-san=k3s.local")
+            .with_server_arg("--cluster-cidr=10.42.0.0/16")
+            .with_server_arg("--disable=servicelb,runtimes")
+            .with_config_yaml(
+                "tls-san: [k3s.local, 127.0.0.2]\nkubelet-arg: max-pods=250\ndisable-helm-controller: true\n\
+                 cluster-cidr: 10.42.0.0/16\n",
+            )
+            .with_cluster_init(true);
+
+        assert!(!k3s.features.service_lb);
+        assert!(!k3s.features.helm_controller);
+
+        let config = k3s.server_config.render(&k3s.features);
+        let expected: Mapping = serde_yaml::from_str(
+            r#"
+snapshotter: native
+disable: [traefik, servicelb, runtimes]
+disable-helm-controller: true
+cluster-init: true
+tls-san: [k3s.local, 127.0.0.2]
+cluster-cidr: 10.42.0.0/16
+kubelet-arg: [max-pods=250]
+"#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+
+        assert!(matches!(
+            K3s::default()
+                .try_with_server_arg("--cluster-cidr=a")
+                .unwrap()
+                .try_with_server_arg("--cluster-cidr=b"),
+            Err(Error::RuntimeConfig(_))
+        ));
+        assert!(matches!(
+            K3s::default()
+                .with_cluster_init(true)
+                .try_with_config_yaml("datastore-endpoint: postgres://db"),
+            Err(Error::RuntimeConfig(_))
+        ));
+        assert!(matches!(
+            K3s::default().try_with_server_arg("--https-listen-port=7443"),
+            Err(Error::RuntimeConfig(_))
+        ));
+        assert!(matches!(
+            K3s::default().try_with_server_arg("tls-san"),
+            Err(Error::RuntimeConfig(_))
+        ));
+    }
+
+    #[test]
+    fn render_feature_gates_and_admission_plugins() {
+        let k3s = K3s::default()
+            .with_server_arg("--kube-apiserver-arg=v=2")
+            .with_feature_gate("SidecarContainers", true)
+            .with_feature_gate("ValidatingAdmissionPolicy", true)
+            .with_admission_plugin("NamespaceAutoProvision")
+            .with_disabled_admission_plugin("NamespaceLifecycle")
+            .with_pod_security_level(K3sPodSecurityLevel::Restricted);
+
+        let config = k3s.server_config.render(&k3s.features);
+        let gates = "feature-gates=SidecarContainers=true,ValidatingAdmissionPolicy=true";
+        assert_eq!(
+            config["kube-apiserver-arg"],
+            serde_yaml::to_value([
+                "v=2",
+                "enable-admission-plugins=NamespaceAutoProvision",
+                "disable-admission-plugins=NamespaceLifecycle",
+                "admission-control-config-file=/etc/rancher/k3s/admission-config.yaml",
+                gates,
+            ])
+            .unwrap()
+        );
+        assert_eq!(config["kubelet-arg"], serde_yaml::to_value([gates]).unwrap());
+        assert_eq!(
+            k3s.server_config.agent_args(),
+            vec![format!("--kubelet-arg={gates}"), format!("--kube-proxy-arg={gates}")]
+        );
+        assert_eq!(k3s.config_files.len(), 2);
+        assert!(pod_security_admission_config(K3sPodSecurityLevel::Restricted).contains("enforce: \"restricted\""));
+
+        assert_eq!(
+            "baseline".parse::<K3sPodSecurityLevel>().unwrap(),
+            K3sPodSecurityLevel::Baseline
+        );
+        assert!(matches!(
+            "strict".parse::<K3sPodSecurityLevel>(),
+            Err(Error::RuntimeConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+use super::{wait::has_condition, K3s, FIELD_MANAGER};
+use crate::{Error, Result};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::wait::{await_condition, Condition as _},
+    Api, Client, ResourceExt as _,
+};
+use serde::Deserialize as _;
+use std::time::Duration;
+
+pub const K3S_DEFAULT_CRDS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// CRDs to apply: (multi-document) YAML manifest or `CustomResourceDefinition` value.
+#[derive(Debug, Clone)]
+pub enum K3sCrd {
+    Yaml(String),
+    Definition(Box<CustomResourceDefinition>),
+}
+
+impl From<&str> for K3sCrd {
+    fn from(value: &str) -> Self {
+        Self::Yaml(value.to_string())
+    }
+}
+
+impl From<String> for K3sCrd {
+    fn from(value: String) -> Self {
+        Self::Yaml(value)
+    }
+}
+
+impl From<CustomResourceDefinition> for K3sCrd {
+    fn from(value: CustomResourceDefinition) -> Self {
+        Self::Definition(Box::new(value))
+    }
+}
+
+impl K3sCrd {
+    fn into_definitions(self) -> Result<Vec<CustomResourceDefinition>> {
+        match self {
+            K3sCrd::Definition(crd) => Ok(vec![*crd]),
+            K3sCrd::Yaml(yaml) => {
+                let mut crds = vec![];
+                for document in serde_yaml::Deserializer::from_str(&yaml) {
+                    let value = serde_yaml::Value::deserialize(document)
+                        .map_err(|e| Error::RuntimeConfig(format!("invalid CRD manifest: {e}")))?;
+                    if value.is_null() {
+                        continue;
+                    }
+                    let crd = serde_yaml::from_value(value)
+                        .map_err(|e| Error::RuntimeConfig(format!("invalid CRD manifest: {e}")))?;
+                    crds.push(crd);
+                }
+                Ok(crds)
+            }
+        }
+    }
+}
+
+impl K3s {
+    /// Adds CRDs which are applied at startup, panics on invalid CRD manifest, see [`K3s::try_with_crds`].
+    pub fn with_crds(self, crds: impl IntoIterator<Item = impl Into<K3sCrd>>) -> Self {
+        self.try_with_crds(crds).unwrap()
+    }
+
+    /// Adds CRDs to the startup manifests, so container start is blocked until they are established
+    /// and have names accepted, see [`K3s::with_manifest`].
+    pub fn try_with_crds(self, crds: impl IntoIterator<Item = impl Into<K3sCrd>>) -> Result<Self> {
+        let mut k3s = self;
+        for crd in crds {
+            for definition in crd.into().into_definitions()? {
+                let yaml = serde_yaml::to_string(&definition)
+                    .map_err(|e| Error::RuntimeConfig(format!("invalid CRD manifest: {e}")))?;
+                k3s = k3s.try_with_manifest(format!("crd-{}", definition.name_any()), yaml)?;
+            }
+        }
+
+        Ok(k3s)
+    }
+}
+
+/// Server-side applies CRDs to the running cluster and waits until all of them are established
+/// and have names accepted, [`K3S_DEFAULT_CRDS_TIMEOUT`] is a reasonable timeout.
+pub async fn apply_crds(
+    client: &Client,
+    crds: impl IntoIterator<Item = impl Into<K3sCrd>>,
+    timeout: Duration,
+) -> Result<Vec<CustomResourceDefinition>> {
+    let mut definitions = vec![];
+    for crd in crds {
+        definitions.extend(crd.into().into_definitions()?);
+    }
+
+    let api: Api<CustomResourceDefinition> = Api::all(client.clone());
+    let params = PatchParams::apply(FIELD_MANAGER).force();
+    for crd in &definitions {
+        api.patch(&crd.name_any(), &params, &Patch::Apply(crd)).await?;
+    }
+
+    let mut applied = vec![];
+    for crd in &definitions {
+        let name = crd.name_any();
+        let ready = has_condition::<CustomResourceDefinition>("Established").and(has_condition("NamesAccepted"));
+        let crd = tokio::time::timeout(timeout, await_condition(api.clone(), &name, ready))
+            .await
+            .map_err(|_| Error::Timeout(format!("CRD `{name}` isn't established in {timeout:?}")))??;
+        applied.extend(crd);
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRDS: &str = r#"
+apiVersion: apiextensions.k8s.io/v1
+kind: CustomResourceDefinition
+metadata:
+  name: foos.example.com
+spec:
+  group: example.com
+  names:
+    kind: Foo
+    plural: foos
+  scope: Namespaced
+  versions:
+    - name: v1
+      served: true
+      storage: true
+      schema:
+        openAPIV3Schema:
+          type: object
+---
+apiVersion: apiextensions.k8s.io/v1
+kind: CustomResourceDefinition
+metadata:
+  name: bars.example.com
+spec:
+  group: example.com
+  names:
+    kind: Bar
+    plural: bars
+  scope: Cluster
+  versions: []
+"#;
+
+    #[test]
+    fn crds_from_yaml() {
+        let crds = K3sCrd::from(CRDS).into_definitions().unwrap();
+        assert_eq!(crds.len(), 2);
+        assert_eq!(crds[0].name_any(), "foos.example.com");
+        assert_eq!(crds[1].spec.names.kind, "Bar");
+
+        let k3s = K3s::default().with_crds([CRDS]);
+        assert_eq!(k3s.manifests.len(), 2);
+        assert_eq!(k3s.manifests[0].kind(), "CustomResourceDefinition");
+        assert_eq!(k3s.manifests[1].name(), "bars.example.com");
+        assert!(k3s.wait_for_manifests_cmd().is_some());
+        assert!(matches!(
+            k3s.try_with_crds([crds[1].clone()]),
+            Err(Error::RuntimeConfig(_))
+        ));
+
+        assert!(matches!(
+            K3sCrd::from("kind: [").into_definitions(),
+            Err(Error::RuntimeConfig(_))
+        ));
+        assert!(matches!(
+            K3s::default().try_with_crds(["kind: ["]),
+            Err(Error::RuntimeConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,368 @@
+use super::TestNamespace;
+use crate::{get_runtime_folder, Result};
+use futures::{AsyncBufReadExt as _, StreamExt as _};
+use k8s_openapi::{
+    api::core::v1::{Event, Pod},
+    chrono::{DateTime, Utc},
+};
+use kube::{
+    api::LogParams,
+    runtime::{watcher, WatchStreamExt as _},
+    Api, Client, ResourceExt as _,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tokio::task::JoinHandle;
+
+/// Folder under the runtime folder with diagnostics of the failed tests, one subfolder per test.
+pub const K3S_DIAGNOSTICS_FOLDER: &str = "k3s-diagnostics";
+
+const EVENTS_FILE_NAME: &str = "events.log";
+const PODS_FILE_NAME: &str = "pods.yaml";
+const LOGS_FOLDER_NAME: &str = "logs";
+
+/// What is recorded so far, it survives deletion of the namespaces.
+#[derive(Debug, Default)]
+struct Records {
+    /// Events by UID, the latest version of each event is kept.
+    events: BTreeMap<String, Event>,
+    /// Pods by `namespace/name`.
+    pods: BTreeMap<String, Pod>,
+    /// Logs by `namespace/pod/container/restart`, every run of the container is recorded once its key is added.
+    logs: BTreeMap<String, String>,
+}
+
+/// Records Events, pod statuses and container logs of the namespaces while the test runs,
+/// dumps them (see [`TestDiagnostics::dump`]) if the test panics or guard isn't marked as successful.
+pub struct TestDiagnostics {
+    client: Client,
+    test_name: String,
+    records: Arc<Mutex<Records>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    succeeded: bool,
+}
+
+impl TestDiagnostics {
+    /// Starts watching the namespaces, should be called inside the test runtime.
+    pub fn start(client: &Client, namespaces: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let test_name = thread::current().name().unwrap_or("unnamed-test").to_string();
+        let mut diagnostics = Self {
+            client: client.clone(),
+            test_name,
+            records: Arc::default(),
+            tasks: Arc::default(),
+            succeeded: false,
+        };
+        for namespace in namespaces {
+            diagnostics.watch_namespace(namespace);
+        }
+
+        diagnostics
+    }
+
+    /// Starts watching one more namespace.
+    pub fn watch_namespace(&mut self, namespace: impl Into<String>) {
+        let namespace = namespace.into();
+        let events = tokio::spawn(record_events(
+            Api::namespaced(self.client.clone(), &namespace),
+            self.records.clone(),
+        ));
+        let pods = tokio::spawn(record_pods(
+            Api::namespaced(self.client.clone(), &namespace),
+            self.records.clone(),
+            self.tasks.clone(),
+        ));
+        self.tasks.lock().unwrap().extend([events, pods]);
+    }
+
+    /// Marks the test as successful, so nothing is dumped when guard is dropped.
+    pub fn succeed(&mut self) {
+        self.succeeded = true;
+    }
+
+    /// Writes recorded diagnostics to `<runtime folder>/k3s-diagnostics/<test name>` and returns this folder,
+    /// they are printed to stderr if the runtime folder isn't available.
+    pub fn dump(&self) -> Result<Option<PathBuf>> {
+        let records = self.records.lock().unwrap();
+        let Ok(runtime_folder) = get_runtime_folder() else {
+            eprintln!("=== Diagnostics of `{}` ===", self.test_name);
+            eprintln!("--- Events ---\n{}", render_events(&records.events));
+            eprintln!("--- Pods ---\n{}", render_pods(&records.pods));
+            for (container, logs) in &records.logs {
+                eprintln!("--- Logs of {container} ---\n{logs}");
+            }
+            return Ok(None);
+        };
+
+        let folder = PathBuf::from(runtime_folder)
+            .join(K3S_DIAGNOSTICS_FOLDER)
+            .join(folder_name(&self.test_name));
+        // Files of the previous run of the same test shouldn't look like current ones
+        match std::fs::remove_dir_all(&folder) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        std::fs::create_dir_all(folder.join(LOGS_FOLDER_NAME))?;
+        std::fs::write(folder.join(EVENTS_FILE_NAME), render_events(&records.events))?;
+        std::fs::write(folder.join(PODS_FILE_NAME), render_pods(&records.pods))?;
+        for (container, logs) in &records.logs {
+            let file = format!("{}.log", folder_name(container));
+            std::fs::write(folder.join(LOGS_FOLDER_NAME).join(file), logs)?;
+        }
+        eprintln!(
+            "Diagnostics of `{}` are written to {}",
+            self.test_name,
+            folder.display()
+        );
+
+        Ok(Some(folder))
+    }
+}
+
+impl Drop for TestDiagnostics {
+    fn drop(&mut self) {
+        self.tasks.lock().unwrap().iter().for_each(JoinHandle::abort);
+
+        if thread::panicking() || !self.succeeded {
+            if let Err(e) = self.dump() {
+                eprintln!("Can't dump diagnostics of `{}`: {e}", self.test_name);
+            }
+        }
+    }
+}
+
+impl TestNamespace {
+    /// Starts recording diagnostics of the test namespace, guard should be dropped before the namespace.
+    pub fn diagnostics(&self) -> TestDiagnostics {
+        TestDiagnostics::start(&self.client(), [self.name()])
+    }
+}
+
+async fn record_events(api: Api<Event>, records: Arc<Mutex<Records>>) {
+    let mut events = watcher(api, watcher::Config::default())
+        .default_backoff()
+        .applied_objects()
+        .boxed();
+    while let Some(event) = events.next().await {
+        if let Ok(event) = event {
+            let uid = event.uid().unwrap_or_default();
+            records.lock().unwrap().events.insert(uid, event);
+        }
+    }
+}
+
+async fn record_pods(api: Api<Pod>, records: Arc<Mutex<Records>>, tasks: Arc<Mutex<Vec<JoinHandle<()>>>>) {
+    let mut pods = watcher(api.clone(), watcher::Config::default())
+        .default_backoff()
+        .applied_objects()
+        .boxed();
+    while let Some(pod) = pods.next().await {
+        let Ok(pod) = pod else {
+            continue;
+        };
+
+        let name = pod.name_any();
+        let key = format!("{}/{name}", pod.namespace().unwrap_or_default());
+        let mut recorded = records.lock().unwrap();
+        for run in container_runs(&pod) {
+            let run_key = format!("{key}/{}/{}", run.container, run.restart);
+            if recorded.logs.contains_key(&run_key) {
+                continue;
+            }
+            recorded.logs.insert(run_key.clone(), String::new());
+
+            let follow = record_logs(api.clone(), name.clone(), run, run_key, records.clone());
+            tasks.lock().unwrap().push(tokio::spawn(follow));
+        }
+        recorded.pods.insert(key, pod);
+    }
+}
+
+/// Follows logs of the current run or reads logs of the previous (already terminated) one.
+async fn record_logs(api: Api<Pod>, pod: String, run: ContainerRun, key: String, records: Arc<Mutex<Records>>) {
+    let params = LogParams {
+        container: Some(run.container),
+        follow: !run.previous,
+        previous: run.previous,
+        ..Default::default()
+    };
+    let Ok(logs) = api.log_stream(&pod, &params).await else {
+        return;
+    };
+
+    let mut lines = logs.lines().boxed();
+    while let Some(Ok(line)) = lines.next().await {
+        if let Some(logs) = records.lock().unwrap().logs.get_mut(&key) {
+            logs.push_str(&line);
+            logs.push('\n');
+        }
+    }
+}
+
+/// Run of the container whose logs are available.
+#[derive(Debug, PartialEq, Eq)]
+struct ContainerRun {
+    container: String,
+    /// Restart count of the container when this run was started.
+    restart: i32,
+    /// Run is the previous one, so its logs are read using `previous` flag.
+    previous: bool,
+}
+
+/// Runs of the containers (including init ones): current run if it's running or terminated and
+/// previous one if container was restarted, like in `CrashLoopBackOff` state.
+fn container_runs(pod: &Pod) -> Vec<ContainerRun> {
+    let Some(status) = pod.status.as_ref() else {
+        return vec![];
+    };
+
+    let mut runs = vec![];
+    for status in status
+        .init_container_statuses
+        .iter()
+        .chain(status.container_statuses.iter())
+        .flatten()
+    {
+        if status.restart_count > 0 {
+            runs.push(ContainerRun {
+                container: status.name.clone(),
+                restart: status.restart_count - 1,
+                previous: true,
+            });
+        }
+        if status
+            .state
+            .as_ref()
+            .is_some_and(|state| state.running.is_some() || state.terminated.is_some())
+        {
+            runs.push(ContainerRun {
+                container: status.name.clone(),
+                restart: status.restart_count,
+                previous: false,
+            });
+        }
+    }
+
+    runs
+}
+
+fn render_events(events: &BTreeMap<String, Event>) -> String {
+    let time = |event: &Event| -> Option<DateTime<Utc>> {
+        event
+            .last_timestamp
+            .as_ref()
+            .map(|t| t.0)
+            .or_else(|| event.event_time.as_ref().map(|t| t.0))
+            .or_else(|| event.metadata.creation_timestamp.as_ref().map(|t| t.0))
+    };
+
+    let mut events = events.values().collect::<Vec<_>>();
+    events.sort_by_key(|event| time(event));
+
+    let mut rendered = String::new();
+    for event in events {
+        let object = &event.involved_object;
+        let _ = writeln!(
+            rendered,
+            "{} {} {} {}/{}: {}",
+            time(event).map(|t| t.to_rfc3339()).unwrap_or_default(),
+            event.type_.as_deref().unwrap_or_default(),
+            event.reason.as_deref().unwrap_or_default(),
+            object.kind.as_deref().unwrap_or_default(),
+            object.name.as_deref().unwrap_or_default(),
+            event.message.as_deref().unwrap_or_default(),
+        );
+    }
+
+    rendered
+}
+
+/// Statuses of the pods as YAML mapping by `namespace/name`.
+fn render_pods(pods: &BTreeMap<String, Pod>) -> String {
+    let statuses = pods
+        .iter()
+        .map(|(key, pod)| (key, &pod.status))
+        .collect::<BTreeMap<_, _>>();
+    serde_yaml::to_string(&statuses).unwrap_or_default()
+}
+
+/// Test names (like `tests::it_works`) and `namespace/pod/container` keys as file names.
+fn folder_name(name: &str) -> String {
+    name.replace("::", "-")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_diagnostics() {
+        let event = |uid: &str, time: &str, reason: &str| -> Event {
+            serde_json::from_value(serde_json::json!({
+                "metadata": {"name": uid, "uid": uid},
+                "involvedObject": {"kind": "Pod", "name": "web-0"},
+                "type": "Warning",
+                "reason": reason,
+                "message": "message",
+                "lastTimestamp": time
+            }))
+            .unwrap()
+        };
+        let events = BTreeMap::from([
+            ("a".to_string(), event("a", "2024-10-01T10:00:05Z", "BackOff")),
+            ("b".to_string(), event("b", "2024-10-01T10:00:00Z", "Failed")),
+        ]);
+        assert_eq!(
+            render_events(&events),
+            "2024-10-01T10:00:00+00:00 Warning Failed Pod/web-0: message\n\
+             2024-10-01T10:00:05+00:00 Warning BackOff Pod/web-0: message\n"
+        );
+
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "web-0", "namespace": "apps"},
+            "status": {
+                "phase": "Running",
+                "initContainerStatuses": [{"name": "init", "image": "busybox", "imageID": "", "ready": false,
+                    "restartCount": 0, "state": {"terminated": {"exitCode": 0}}}],
+                "containerStatuses": [
+                    {"name": "web", "image": "nginx", "imageID": "", "ready": true, "restartCount": 0,
+                        "state": {"running": {}}},
+                    {"name": "sidecar", "image": "envoy", "imageID": "", "ready": false, "restartCount": 0,
+                        "state": {"waiting": {"reason": "ContainerCreating"}}},
+                    {"name": "worker", "image": "app", "imageID": "", "ready": false, "restartCount": 3,
+                        "state": {"waiting": {"reason": "CrashLoopBackOff"}}}
+                ]
+            }
+        }))
+        .unwrap();
+        let run = |container: &str, restart: i32, previous: bool| ContainerRun {
+            container: container.to_string(),
+            restart,
+            previous,
+        };
+        assert_eq!(
+            container_runs(&pod),
+            vec![run("init", 0, false), run("web", 0, false), run("worker", 2, true)]
+        );
+        let pods = render_pods(&BTreeMap::from([("apps/web-0".to_string(), pod)]));
+        assert!(pods.starts_with("apps/web-0:\n"), "{pods}");
+        assert!(pods.contains("\n  phase: Running\n"), "{pods}");
+
+        assert_eq!(folder_name("tests::it_works"), "tests-it_works");
+        assert_eq!(folder_name("apps/web-0/web"), "apps_web-0_web");
+    }
+}
@@ -0,0 +1,249 @@
+use super::{wait::has_condition, K3s, FIELD_MANAGER};
+use crate::{Error, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use k8s_openapi::api::{batch::v1::Job, core::v1::Pod};
+use kube::{
+    api::{ApiResource, DynamicObject, GroupVersionKind, ListParams, LogParams, Patch, PatchParams},
+    runtime::wait::{await_condition, Condition as _},
+    Api, Client,
+};
+use serde_json::json;
+use std::{path::Path, time::Duration};
+
+/// Namespace where `HelmChart` resources and helm-install jobs live.
+pub const K3S_HELM_CHARTS_NAMESPACE: &str = "kube-system";
+pub const K3S_DEFAULT_HELM_INSTALL_TIMEOUT: Duration = Duration::from_secs(300);
+/// Retries of the helm-install job, helm-controller default is 1000 so failed install would never finish.
+pub const K3S_DEFAULT_HELM_BACK_OFF_LIMIT: u32 = 1;
+
+const HELM_CHART_API_GROUP: &str = "helm.cattle.io";
+const HELM_CHART_API_VERSION: &str = "v1";
+const HELM_CHART_KIND: &str = "HelmChart";
+const HELM_CHART_PLURAL: &str = "helmcharts";
+pub(crate) const HELM_INSTALL_JOB_PREFIX: &str = "helm-install-";
+
+/// Helm chart which is installed by the k3s helm-controller using `HelmChart` resource.
+#[derive(Debug, Clone)]
+pub struct K3sHelmChart {
+    name: String,
+    chart_content: Vec<u8>,
+    values: Option<String>,
+    target_namespace: String,
+    install_timeout: Duration,
+    back_off_limit: u32,
+}
+
+impl K3sHelmChart {
+    /// Creates chart from the packaged (`.tgz`) chart content.
+    pub fn from_bytes(name: impl Into<String>, chart_content: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: name.into(),
+            chart_content: chart_content.into(),
+            values: None,
+            target_namespace: "default".to_string(),
+            install_timeout: K3S_DEFAULT_HELM_INSTALL_TIMEOUT,
+            back_off_limit: K3S_DEFAULT_HELM_BACK_OFF_LIMIT,
+        }
+    }
+
+    /// Creates chart from the packaged (`.tgz`) chart file.
+    pub fn from_file(name: impl Into<String>, path: impl AsRef<Path>) -> Result<Self> {
+        let chart_content = std::fs::read(path)?;
+        Ok(Self::from_bytes(name, chart_content))
+    }
+
+    pub fn with_values(self, values_yaml: impl Into<String>) -> Self {
+        Self {
+            values: Some(values_yaml.into()),
+            ..self
+        }
+    }
+
+    pub fn with_target_namespace(self, namespace: impl Into<String>) -> Self {
+        Self {
+            target_namespace: namespace.into(),
+            ..self
+        }
+    }
+
+    /// Timeout of [`K3sHelmChart::wait_installed`].
+    pub fn with_install_timeout(self, timeout: Duration) -> Self {
+        Self {
+            install_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Number of helm-install job retries before it's failed.
+    pub fn with_back_off_limit(self, back_off_limit: u32) -> Self {
+        Self { back_off_limit, ..self }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Name of the job which is created by helm-controller to install the chart.
+    pub fn install_job_name(&self) -> String {
+        format!("{HELM_INSTALL_JOB_PREFIX}{}", self.name)
+    }
+
+    /// Renders `HelmChart` manifest.
+    pub fn to_yaml(&self) -> String {
+        let mut spec = json!({
+            "chartContent": BASE64.encode(&self.chart_content),
+            "targetNamespace": self.target_namespace,
+            "createNamespace": true,
+            "backOffLimit": self.back_off_limit,
+        });
+        if let Some(values) = &self.values {
+            spec["valuesContent"] = json!(values);
+        }
+
+        let chart = json!({
+            "apiVersion": format!("{HELM_CHART_API_GROUP}/{HELM_CHART_API_VERSION}"),
+            "kind": HELM_CHART_KIND,
+            "metadata": {
+                "name": self.name,
+                "namespace": K3S_HELM_CHARTS_NAMESPACE,
+            },
+            "spec": spec,
+        });
+
+        serde_yaml::to_string(&chart).unwrap()
+    }
+
+    /// Creates or updates `HelmChart` resource in the running cluster.
+    pub async fn install(&self, client: &Client) -> Result<()> {
+        let gvk = GroupVersionKind::gvk(HELM_CHART_API_GROUP, HELM_CHART_API_VERSION, HELM_CHART_KIND);
+        let resource = ApiResource::from_gvk_with_plural(&gvk, HELM_CHART_PLURAL);
+        let api: Api<DynamicObject> = Api::namespaced_with(client.clone(), K3S_HELM_CHARTS_NAMESPACE, &resource);
+
+        let chart: DynamicObject = serde_yaml::from_str(&self.to_yaml())
+            .map_err(|e| Error::RuntimeConfig(format!("invalid HelmChart manifest: {e}")))?;
+        api.patch(
+            &self.name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(chart),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Waits until helm-install job succeeds, returns [`Error::HelmInstall`] with logs of the failed pods
+    /// if it fails after all retries or times out after failed attempts.
+    pub async fn wait_installed(&self, client: &Client) -> Result<()> {
+        let jobs: Api<Job> = Api::namespaced(client.clone(), K3S_HELM_CHARTS_NAMESPACE);
+        let job_name = self.install_job_name();
+        let timeout = self.install_timeout;
+
+        // Failed pods are retried by the job, so only its `Failed` condition is final
+        let finished = has_condition::<Job>("Complete").or(has_condition("Failed"));
+        let job = match tokio::time::timeout(timeout, await_condition(jobs, &job_name, finished)).await {
+            Ok(job) => job?,
+            Err(_) => {
+                let logs = self.install_logs(client).await?;
+                return Err(if logs.is_empty() {
+                    Error::Timeout(format!("helm chart `{}` isn't installed in {timeout:?}", self.name))
+                } else {
+                    Error::HelmInstall {
+                        chart: self.name.clone(),
+                        logs,
+                    }
+                });
+            }
+        };
+
+        if has_condition("Complete")(job.as_ref()) {
+            Ok(())
+        } else {
+            Err(Error::HelmInstall {
+                chart: self.name.clone(),
+                logs: self.install_logs(client).await?,
+            })
+        }
+    }
+
+    /// Logs of the failed helm-install attempts, pods may be failed or restarted depending on the restart policy.
+    async fn install_logs(&self, client: &Client) -> Result<String> {
+        let pods: Api<Pod> = Api::namespaced(client.clone(), K3S_HELM_CHARTS_NAMESPACE);
+        let selector = ListParams::default().labels(&format!("job-name={}", self.install_job_name()));
+
+        let mut logs = String::new();
+        for pod in pods.list(&selector).await? {
+            let status = pod.status.unwrap_or_default();
+            let failed = status.phase.as_deref() == Some("Failed");
+            let restarted = status
+                .container_statuses
+                .unwrap_or_default()
+                .iter()
+                .any(|c| c.restart_count > 0);
+            if !failed && !restarted {
+                continue;
+            }
+
+            let name = pod.metadata.name.unwrap_or_default();
+            let params = LogParams {
+                previous: restarted && !failed,
+                ..LogParams::default()
+            };
+            let pod_logs = pods.logs(&name, &params).await?;
+            logs.push_str(&format!("--- {name} ---\n{pod_logs}\n"));
+        }
+
+        Ok(logs)
+    }
+}
+
+impl K3s {
+    /// Adds helm chart which is installed by helm-controller at startup,
+    /// container start is blocked until helm-install job is completed and fails if the job fails.
+    pub fn with_helm_chart(self, chart: K3sHelmChart) -> Self {
+        self.with_helm_controller(true)
+            .with_manifest(format!("helm-chart-{}", chart.name), chart.to_yaml())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helm_chart_manifest() {
+        let chart = K3sHelmChart::from_bytes("test", b"chart".to_vec())
+            .with_values("replicaCount: 2\n")
+            .with_target_namespace("apps");
+        let manifest: serde_yaml::Value = serde_yaml::from_str(&chart.to_yaml()).unwrap();
+
+        assert_eq!(manifest["kind"], "HelmChart");
+        assert_eq!(manifest["metadata"]["namespace"], K3S_HELM_CHARTS_NAMESPACE);
+        assert_eq!(manifest["spec"]["chartContent"], "Y2hhcnQ=");
+        assert_eq!(manifest["spec"]["targetNamespace"], "apps");
+        assert_eq!(manifest["spec"]["valuesContent"], "replicaCount: 2\n");
+        assert_eq!(manifest["spec"]["backOffLimit"], K3S_DEFAULT_HELM_BACK_OFF_LIMIT);
+        assert_eq!(chart.install_job_name(), "helm-install-test");
+        assert_eq!(chart.install_timeout, K3S_DEFAULT_HELM_INSTALL_TIMEOUT);
+
+        let chart = chart.with_back_off_limit(5);
+        let manifest: serde_yaml::Value = serde_yaml::from_str(&chart.to_yaml()).unwrap();
+        assert_eq!(manifest["spec"]["backOffLimit"], 5);
+    }
+
+    #[test]
+    fn helm_install_job_conditions() {
+        let job: Job = serde_json::from_value(json!({
+            "status": {"failed": 3, "conditions": [{"type": "Failed", "status": "False"}]}
+        }))
+        .unwrap();
+        assert!(!has_condition::<Job>("Failed")(Some(&job)));
+        assert!(!has_condition::<Job>("Complete")(Some(&job)));
+
+        let job: Job = serde_json::from_value(json!({
+            "status": {"failed": 7, "conditions": [{"type": "Failed", "status": "True"}]}
+        }))
+        .unwrap();
+        assert!(has_condition::<Job>("Failed")(Some(&job)));
+        assert!(!has_condition::<Job>("Complete")(None));
+    }
+}
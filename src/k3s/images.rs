@@ -0,0 +1,162 @@
+use super::{K3s, EXEC_EXIT_CODE_POLL_INTERVAL};
+use crate::{Error, Result};
+use futures::{StreamExt as _, TryStreamExt as _};
+use std::path::{Path, PathBuf};
+use testcontainers::{
+    bollard::exec::{CreateExecOptions, StartExecResults},
+    core::client::docker_client_instance,
+    ContainerAsync, CopyDataSource, CopyToContainer, Image, TestcontainersError,
+};
+use tokio::io::{AsyncRead, AsyncWriteExt as _};
+use tokio_util::io::StreamReader;
+
+/// Folder which k3s scans for image archives during agent startup.
+pub const K3S_AGENT_IMAGES_FOLDER: &str = "/var/lib/rancher/k3s/agent/images";
+
+const CONTAINERD_NAMESPACE: &str = "k8s.io";
+
+impl K3s {
+    /// Adds image archive (`docker save` or OCI tarball) which will be imported by k3s at startup.
+    /// File name should have one of the extensions recognized by k3s: `.tar`, `.tar.gz`, `.tar.zst`, etc.
+    /// In [`K3sCluster`](super::K3sCluster) archives are copied to every agent as well.
+    pub fn with_image_archive(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let target = image_archive_target(self.image_archives.len(), &path);
+
+        let mut image_archives = self.image_archives;
+        image_archives.push(CopyToContainer::new(CopyDataSource::File(path), target));
+
+        Self { image_archives, ..self }
+    }
+
+    /// Imports image archives into containerd of the running cluster,
+    /// server should run with agent enabled (see [`K3s::with_agent`]) since there is no containerd otherwise.
+    pub async fn load_images(
+        container: &ContainerAsync<K3s>,
+        archives: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> Result<()> {
+        ensure_agent(container)?;
+        for archive in archives {
+            let file = tokio::fs::File::open(archive.as_ref()).await?;
+            import_images(container, file).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Exports images from the local Docker daemon and imports them into containerd of the running cluster,
+    /// server should run with agent enabled.
+    pub async fn load_docker_images(
+        container: &ContainerAsync<K3s>,
+        images: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<()> {
+        ensure_agent(container)?;
+        let images = images.into_iter().map(|i| i.as_ref().to_string()).collect::<Vec<_>>();
+        let images = images.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let docker = docker_client_instance().await.map_err(TestcontainersError::from)?;
+        let archive = docker.export_images(&images).map_err(std::io::Error::other);
+
+        import_images(container, StreamReader::new(archive)).await
+    }
+}
+
+fn ensure_agent(container: &ContainerAsync<K3s>) -> Result<()> {
+    if container.image().features.agent {
+        Ok(())
+    } else {
+        Err(Error::RuntimeConfig(
+            "k3s server runs without agent, so there is no containerd to import images to".into(),
+        ))
+    }
+}
+
+/// Destination of the archive in the images folder, index prefix keeps archives with the same file name apart.
+fn image_archive_target(index: usize, path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| "images.tar".to_string());
+
+    format!("{K3S_AGENT_IMAGES_FOLDER}/{index:03}-{file_name}")
+}
+
+/// Streams image archive to the `ctr images import` command running inside the container.
+pub(crate) async fn import_images<I: Image>(
+    container: &ContainerAsync<I>,
+    mut archive: impl AsyncRead + Unpin,
+) -> Result<()> {
+    let cmd = vec!["k3s", "ctr", "-n", CONTAINERD_NAMESPACE, "images", "import", "-"];
+    let docker = docker_client_instance().await.map_err(TestcontainersError::from)?;
+    let exec = docker
+        .create_exec(
+            container.id(),
+            CreateExecOptions {
+                cmd: Some(cmd.clone()),
+                attach_stdin: Some(true),
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let StartExecResults::Attached { mut output, mut input } = docker.start_exec(&exec.id, None).await? else {
+        return Err(Error::RuntimeConfig("unable to attach to image import command".into()));
+    };
+
+    let write = async {
+        tokio::io::copy(&mut archive, &mut input).await?;
+        input.shutdown().await
+    };
+    let read = async {
+        let mut log = String::new();
+        while let Some(chunk) = output.next().await {
+            log.push_str(&chunk?.to_string());
+        }
+        Ok::<_, Error>(log)
+    };
+    let (written, log) = tokio::join!(write, read);
+    let log = log?;
+    written?;
+
+    let code = loop {
+        if let Some(code) = docker.inspect_exec(&exec.id).await?.exit_code {
+            break code;
+        }
+        tokio::time::sleep(EXEC_EXIT_CODE_POLL_INTERVAL).await;
+    };
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(Error::ContainerExec {
+            command: cmd.join(" "),
+            code,
+            stderr: log,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_archive_targets() {
+        assert_eq!(
+            image_archive_target(0, Path::new("/tmp/a/images.tar.zst")),
+            "/var/lib/rancher/k3s/agent/images/000-images.tar.zst"
+        );
+        assert_ne!(
+            image_archive_target(1, Path::new("/tmp/a/images.tar")),
+            image_archive_target(2, Path::new("/tmp/b/images.tar"))
+        );
+
+        let k3s = K3s::default()
+            .with_image_archive("/tmp/a/images.tar")
+            .with_image_archive("/tmp/b/images.tar");
+        let targets = format!("{:?}", k3s.image_archives);
+        assert!(targets.contains("/000-images.tar"), "{targets}");
+        assert!(targets.contains("/001-images.tar"), "{targets}");
+    }
+}
@@ -0,0 +1,169 @@
+use super::{K3s, K3S_TRAEFIK_HTTPS_PORT, K3S_TRAEFIK_HTTP_PORT};
+use crate::{tls, tls::TlsCert, Error, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use http::{header::HOST, uri::Scheme, HeaderValue, Request, Response};
+use http_body_util::{BodyExt as _, Full};
+use hyper::body::Bytes;
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::sync::Arc;
+use testcontainers::ContainerAsync;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::TlsConnector;
+
+/// Secret with the default Traefik certificate in the `kube-system` namespace.
+pub const K3S_INGRESS_TLS_SECRET: &str = "ingress-default-tls";
+
+const INGRESS_TLS_CA_NAME: &str = "k3s ingress CA";
+
+impl K3s {
+    /// Enables Traefik and makes generated certificate for the specified hosts its default one,
+    /// so HTTPS ingresses (with `tls` section) are trusted by [`K3s::ingress_request`].
+    pub fn with_ingress_tls(self, hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let cert = TlsCert::new(INGRESS_TLS_CA_NAME, hosts);
+        let manifest = ingress_tls_manifest(&cert);
+
+        Self {
+            ingress_tls: Some(cert),
+            ..self
+        }
+        .with_traefik(true)
+        .with_manifest("ingress-tls", manifest)
+    }
+
+    /// PEM-encoded CA certificate of the ingress certificate.
+    pub fn ingress_tls_ca(&self) -> Option<&str> {
+        self.ingress_tls.as_ref().and_then(|cert| cert.ca())
+    }
+
+    /// Host-mapped URL of the Traefik HTTP entrypoint, like `http://127.0.0.1:32768`.
+    pub async fn ingress_base_url(container: &ContainerAsync<K3s>) -> Result<String> {
+        let port = container.get_host_port_ipv4(K3S_TRAEFIK_HTTP_PORT).await?;
+        Ok(format!("http://127.0.0.1:{port}"))
+    }
+
+    /// Host-mapped URL of the Traefik HTTPS entrypoint, like `https://127.0.0.1:32769`.
+    pub async fn ingress_https_base_url(container: &ContainerAsync<K3s>) -> Result<String> {
+        let port = container.get_host_port_ipv4(K3S_TRAEFIK_HTTPS_PORT).await?;
+        Ok(format!("https://127.0.0.1:{port}"))
+    }
+
+    /// Sends request through Traefik: request URI (like `https://app.example.com/path`) defines entrypoint
+    /// and `Host` header (and SNI for HTTPS), but connection goes to the host-mapped port of the container.
+    pub async fn ingress_request(
+        container: &ContainerAsync<K3s>,
+        request: Request<Vec<u8>>,
+    ) -> Result<Response<Vec<u8>>> {
+        let (mut parts, body) = request.into_parts();
+        let (host, authority) = match (parts.uri.host(), parts.uri.authority()) {
+            (Some(host), Some(authority)) => (host.to_string(), authority.to_string()),
+            _ => return Err(Error::RuntimeConfig(format!("request URI `{}` has no host", parts.uri))),
+        };
+        let https = parts.uri.scheme() == Some(&Scheme::HTTPS);
+
+        if !parts.headers.contains_key(HOST) {
+            parts
+                .headers
+                .insert(HOST, HeaderValue::from_str(&authority).map_err(http::Error::from)?);
+        }
+        let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        parts.uri = path.parse().map_err(http::Error::from)?;
+        let request = Request::from_parts(parts, Full::new(Bytes::from(body)));
+
+        let port = if https {
+            K3S_TRAEFIK_HTTPS_PORT
+        } else {
+            K3S_TRAEFIK_HTTP_PORT
+        };
+        let port = container.get_host_port_ipv4(port).await?;
+        let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+
+        if https {
+            let ca = container
+                .image()
+                .ingress_tls_ca()
+                .ok_or_else(|| Error::RuntimeConfig("ingress TLS isn't configured".into()))?;
+            let connector = TlsConnector::from(Arc::new(tls::client_config(ca)?));
+            let server_name =
+                ServerName::try_from(host).map_err(|e| Error::RuntimeConfig(format!("invalid ingress host: {e}")))?;
+            let stream = connector.connect(server_name, stream).await?;
+            send_request(stream, request).await
+        } else {
+            send_request(stream, request).await
+        }
+    }
+
+    /// Sends `GET` request through Traefik, see [`K3s::ingress_request`].
+    pub async fn ingress_get(container: &ContainerAsync<K3s>, url: impl AsRef<str>) -> Result<Response<Vec<u8>>> {
+        let request = Request::get(url.as_ref()).body(vec![])?;
+        K3s::ingress_request(container, request).await
+    }
+}
+
+async fn send_request<S>(stream: S, request: Request<Full<Bytes>>) -> Result<Response<Vec<u8>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let (parts, body) = sender.send_request(request).await?.into_parts();
+    let body = body.collect().await?.to_bytes().to_vec();
+
+    Ok(Response::from_parts(parts, body))
+}
+
+/// TLS secret and default Traefik TLS store which uses it.
+fn ingress_tls_manifest(cert: &TlsCert) -> String {
+    format!(
+        r#"apiVersion: v1
+kind: Secret
+metadata:
+  name: {K3S_INGRESS_TLS_SECRET}
+  namespace: kube-system
+type: kubernetes.io/tls
+data:
+  tls.crt: {}
+  tls.key: {}
+---
+apiVersion: traefik.io/v1alpha1
+kind: TLSStore
+metadata:
+  name: default
+  namespace: kube-system
+spec:
+  defaultCertificate:
+    secretName: {K3S_INGRESS_TLS_SECRET}
+"#,
+        BASE64.encode(cert.cert()),
+        BASE64.encode(cert.key())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ingress_tls_manifest_objects() {
+        let k3s = K3s::default().with_traefik(false).with_ingress_tls(["app.local"]);
+        assert!(k3s.features.traefik);
+        assert!(k3s.ingress_tls_ca().is_some());
+        assert_eq!(k3s.manifests.len(), 2);
+
+        let manifest = ingress_tls_manifest(k3s.ingress_tls.as_ref().unwrap());
+        let secret: serde_yaml::Value = serde_yaml::Deserializer::from_str(&manifest)
+            .next()
+            .map(|d| serde::Deserialize::deserialize(d).unwrap())
+            .unwrap();
+        let cert = BASE64.decode(secret["data"]["tls.crt"].as_str().unwrap()).unwrap();
+        assert!(String::from_utf8(cert)
+            .unwrap()
+            .starts_with("-----BEGIN CERTIFICATE-----"));
+    }
+}
@@ -0,0 +1,107 @@
+use super::K3s;
+use crate::Result;
+use kube::config::Kubeconfig;
+use std::{ffi::OsString, path::Path};
+use testcontainers::ContainerAsync;
+
+/// Environment variable which points kube tools to the kubeconfig file.
+pub const KUBECONFIG_ENV: &str = "KUBECONFIG";
+
+impl K3s {
+    /// Writes kubeconfig with the host-mapped API server address for external tools (`kubectl`, `helm`),
+    /// cluster, user and context are named `context_name`, optional namespace becomes default one.
+    ///
+    /// Returns `KUBECONFIG` environment variable pair to pass to [`std::process::Command::envs`].
+    pub async fn write_kubeconfig(
+        container: &ContainerAsync<K3s>,
+        path: impl AsRef<Path>,
+        context_name: impl AsRef<str>,
+        namespace: Option<&str>,
+    ) -> Result<(&'static str, OsString)> {
+        let config = K3s::get_host_kubeconfig(container).await?;
+        let config = rename_kubeconfig(config, context_name.as_ref(), namespace);
+        let config = serde_yaml::to_string(&config).unwrap();
+
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, config).await?;
+
+        Ok(kubeconfig_env(path))
+    }
+}
+
+/// `KUBECONFIG` environment variable pair which points to the specified file.
+pub fn kubeconfig_env(path: impl AsRef<Path>) -> (&'static str, OsString) {
+    (KUBECONFIG_ENV, path.as_ref().as_os_str().to_owned())
+}
+
+/// Renames the only cluster/user/context of the k3s kubeconfig and makes this context current one.
+fn rename_kubeconfig(mut config: Kubeconfig, name: &str, namespace: Option<&str>) -> Kubeconfig {
+    config.clusters.iter_mut().for_each(|c| c.name = name.to_string());
+    config.auth_infos.iter_mut().for_each(|a| a.name = name.to_string());
+    config.contexts.iter_mut().for_each(|c| {
+        c.name = name.to_string();
+        if let Some(context) = c.context.as_mut() {
+            context.cluster = name.to_string();
+            context.user = name.to_string();
+            context.namespace = namespace.map(String::from);
+        }
+    });
+    config.current_context = Some(name.to_string());
+
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KUBECONFIG: &str = r#"
+apiVersion: v1
+kind: Config
+clusters:
+- cluster:
+    certificate-authority-data: Q0E=
+    server: https://127.0.0.1:32768
+  name: default
+contexts:
+- context:
+    cluster: default
+    user: default
+  name: default
+current-context: default
+users:
+- name: default
+  user:
+    client-certificate-data: Q0VSVA==
+    client-key-data: S0VZ
+"#;
+
+    #[test]
+    fn rename_kubeconfig_context() {
+        let config = Kubeconfig::from_yaml(KUBECONFIG).unwrap();
+        let config = rename_kubeconfig(config, "k3s-test", Some("apps"));
+        let config = Kubeconfig::from_yaml(&serde_yaml::to_string(&config).unwrap()).unwrap();
+
+        assert_eq!(config.current_context.as_deref(), Some("k3s-test"));
+        assert_eq!(config.clusters[0].name, "k3s-test");
+        assert_eq!(config.auth_infos[0].name, "k3s-test");
+        assert!(config.auth_infos[0]
+            .auth_info
+            .as_ref()
+            .unwrap()
+            .client_key_data
+            .is_some());
+        let context = config.contexts[0].context.as_ref().unwrap();
+        assert_eq!(context.cluster, "k3s-test");
+        assert_eq!(context.user, "k3s-test");
+        assert_eq!(context.namespace.as_deref(), Some("apps"));
+
+        assert_eq!(
+            kubeconfig_env("/tmp/kubeconfig"),
+            (KUBECONFIG_ENV, "/tmp/kubeconfig".into())
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use super::{helm::HELM_INSTALL_JOB_PREFIX, K3s};
+use crate::{Error, Result};
+use serde::Deserialize as _;
+use std::{path::Path, time::Duration};
+use testcontainers::{core::CmdWaitFor, core::ExecCommand, CopyDataSource, CopyToContainer};
+
+/// Folder which k3s watches for manifests to apply.
+pub const K3S_MANIFESTS_FOLDER: &str = "/var/lib/rancher/k3s/server/manifests";
+pub const K3S_DEFAULT_MANIFESTS_TIMEOUT: Duration = Duration::from_secs(300);
+
+const MANIFEST_FILE_EXTENSIONS: [&str; 3] = ["yaml", "yml", "json"];
+
+/// Reference to a single object from the manifest, enough to find it with `kubectl`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ManifestObject {
+    api_version: String,
+    kind: String,
+    name: String,
+    namespace: Option<String>,
+}
+
+impl ManifestObject {
+    /// Parses all documents of the (multi-document) YAML manifest.
+    pub(crate) fn from_yaml(yaml: &str) -> Result<Vec<Self>> {
+        let mut objects = vec![];
+        for document in serde_yaml::Deserializer::from_str(yaml) {
+            let value = serde_yaml::Value::deserialize(document)
+                .map_err(|e| Error::RuntimeConfig(format!("invalid manifest: {e}")))?;
+            if value.is_null() {
+                continue;
+            }
+
+            let field = |path: &[&str]| {
+                path.iter()
+                    .try_fold(&value, |v, key| v.get(key))
+                    .and_then(|v| v.as_str())
+                    .map(String::from)
+            };
+            let required = |path: &[&str]| {
+                field(path).ok_or_else(|| Error::RuntimeConfig(format!("manifest object has no `{}`", path.join("."))))
+            };
+
+            // Lists are unfolded to separate objects
+            if let Some(items) = value.get("items").and_then(|v| v.as_sequence()) {
+                for item in items {
+                    objects.extend(Self::from_yaml(&serde_yaml::to_string(item).unwrap())?);
+                }
+                continue;
+            }
+
+            objects.push(Self {
+                api_version: required(&["apiVersion"])?,
+                kind: required(&["kind"])?,
+                name: required(&["metadata", "name"])?,
+                namespace: field(&["metadata", "namespace"]),
+            });
+        }
+
+        Ok(objects)
+    }
+
+    pub(crate) fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Resource name in the `kind.version.group/name` form which is unambiguous for `kubectl`.
+    fn resource(&self) -> String {
+        let kind = self.kind.to_lowercase();
+        match self.api_version.split_once('/') {
+            Some((group, version)) => format!("{kind}.{version}.{group}/{}", self.name),
+            None => format!("{kind}/{}", self.name),
+        }
+    }
+
+    /// Shell snippet which waits for the object to appear and become ready.
+    fn wait_script(&self) -> String {
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        let resource = self.resource();
+        let kubectl = format!("kubectl --namespace {namespace}");
+
+        let mut script = format!("until {kubectl} get {resource} >/dev/null 2>&1; do sleep 1; done");
+        // Overall timeout is applied to the whole script, so each step waits infinitely
+        let ready = match self.kind.as_str() {
+            "Deployment" | "StatefulSet" | "DaemonSet" => {
+                Some(format!("{kubectl} rollout status {resource} --timeout=0s"))
+            }
+            "Job" => Some(format!(
+                "{kubectl} wait --for=condition=complete {resource} --timeout=-1s"
+            )),
+            "CustomResourceDefinition" => Some(format!(
+                "{kubectl} wait --for=condition=established {resource} --timeout=-1s && \
+                 {kubectl} wait --for=condition=namesaccepted {resource} --timeout=-1s"
+            )),
+            // Chart is installed by the helm-controller job, which fails once its retries are exhausted
+            "HelmChart" => {
+                let job = format!("job.v1.batch/{HELM_INSTALL_JOB_PREFIX}{}", self.name);
+                let conditions = format!(
+                    r#"{kubectl} get {job} -o jsonpath='{{.status.conditions[?(@.status=="True")].type}}' 2>/dev/null"#
+                );
+                Some(format!(
+                    "until {conditions} | grep -qE 'Complete|Failed'; do sleep 1; done && {conditions} | grep -q Complete"
+                ))
+            }
+            _ => None,
+        };
+        if let Some(ready) = ready {
+            script.push_str(&format!(" && {ready}"));
+        }
+
+        script
+    }
+}
+
+impl K3s {
+    /// Adds manifest which will be applied by k3s at startup, panics on invalid manifest,
+    /// see [`K3s::try_with_manifest`].
+    pub fn with_manifest(self, name: impl Into<String>, yaml: impl Into<String>) -> Self {
+        self.try_with_manifest(name, yaml).unwrap()
+    }
+
+    /// Adds manifest which will be applied by k3s at startup.
+    /// Container start is blocked until all objects from the manifest exist,
+    /// Deployments, StatefulSets and DaemonSets are rolled out, Jobs are completed and CRDs are established.
+    /// Manifest with the same file name can't be added twice.
+    pub fn try_with_manifest(self, name: impl Into<String>, yaml: impl Into<String>) -> Result<Self> {
+        let yaml = yaml.into();
+        let objects = ManifestObject::from_yaml(&yaml)?;
+        self.with_manifest_objects(name, yaml, objects)
+    }
+
+    /// Adds already parsed manifest, file names should be unique since k3s tracks manifests by them.
+    pub(crate) fn with_manifest_objects(
+        self,
+        name: impl Into<String>,
+        yaml: String,
+        objects: Vec<ManifestObject>,
+    ) -> Result<Self> {
+        let name = name.into();
+        let file_name = if MANIFEST_FILE_EXTENSIONS
+            .iter()
+            .any(|ext| name.ends_with(&format!(".{ext}")))
+        {
+            name
+        } else {
+            format!("{name}.yaml")
+        };
+
+        if self.manifest_files.iter().any(|(f, _)| *f == file_name) {
+            return Err(Error::RuntimeConfig(format!("manifest `{file_name}` is already added")));
+        }
+
+        let mut manifests = self.manifests;
+        manifests.extend(objects);
+        let target = format!("{K3S_MANIFESTS_FOLDER}/{file_name}");
+        let mut manifest_files = self.manifest_files;
+        manifest_files.push((
+            file_name,
+            CopyToContainer::new(CopyDataSource::Data(yaml.into_bytes()), target),
+        ));
+
+        Ok(Self {
+            manifests,
+            manifest_files,
+            ..self
+        })
+    }
+
+    /// Adds all `.yaml`, `.yml` and `.json` files from the folder as manifests, panics if folder can't be read
+    /// or has invalid manifests, see [`K3s::try_with_manifest_dir`].
+    pub fn with_manifest_dir(self, path: impl AsRef<Path>) -> Self {
+        self.try_with_manifest_dir(path).unwrap()
+    }
+
+    /// Adds all `.yaml`, `.yml` and `.json` files from the folder as manifests, see [`K3s::try_with_manifest`].
+    pub fn try_with_manifest_dir(self, path: impl AsRef<Path>) -> Result<Self> {
+        let mut files = std::fs::read_dir(path.as_ref())?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.sort();
+
+        files
+            .into_iter()
+            .filter(|f| {
+                f.is_file()
+                    && f.extension()
+                        .is_some_and(|ext| MANIFEST_FILE_EXTENSIONS.contains(&ext.to_string_lossy().as_ref()))
+            })
+            .try_fold(self, |k3s, f| {
+                let name = f.file_name().unwrap().to_string_lossy().to_string();
+                let yaml = std::fs::read_to_string(&f)?;
+                k3s.try_with_manifest(name, yaml)
+            })
+    }
+
+    pub fn with_manifests_timeout(self, timeout: Duration) -> Self {
+        Self {
+            manifests_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Command which blocks until all objects from manifests are ready.
+    pub(crate) fn wait_for_manifests_cmd(&self) -> Option<ExecCommand> {
+        if self.manifests.is_empty() {
+            return None;
+        }
+
+        let script = self
+            .manifests
+            .iter()
+            .map(ManifestObject::wait_script)
+            .collect::<Vec<_>>()
+            .join(" && ");
+        let cmd = vec![
+            "timeout".to_string(),
+            self.manifests_timeout.as_secs().to_string(),
+            "sh".to_string(),
+            "-c".to_string(),
+            script,
+        ];
+
+        Some(ExecCommand::new(cmd).with_cmd_ready_condition(CmdWaitFor::exit_code(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: test
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: web
+  namespace: test
+---
+apiVersion: v1
+kind: List
+items:
+  - apiVersion: batch/v1
+    kind: Job
+    metadata:
+      name: init
+"#;
+
+    #[test]
+    fn parse_manifest_objects() {
+        let objects = ManifestObject::from_yaml(MANIFEST).unwrap();
+
+        assert_eq!(objects.len(), 3);
+        assert_eq!(objects[0].resource(), "namespace/test");
+        assert_eq!(objects[1].resource(), "deployment.v1.apps/web");
+        assert_eq!(objects[1].namespace.as_deref(), Some("test"));
+        assert_eq!(objects[2].resource(), "job.v1.batch/init");
+    }
+
+    #[test]
+    fn manifest_wait_scripts() {
+        let objects = ManifestObject::from_yaml(MANIFEST).unwrap();
+
+        assert_eq!(
+            objects[0].wait_script(),
+            "until kubectl --namespace default get namespace/test >/dev/null 2>&1; do sleep 1; done"
+        );
+        assert!(objects[1]
+            .wait_script()
+            .ends_with("kubectl --namespace test rollout status deployment.v1.apps/web --timeout=0s"));
+        assert!(objects[2]
+            .wait_script()
+            .ends_with("kubectl --namespace default wait --for=condition=complete job.v1.batch/init --timeout=-1s"));
+    }
+
+    #[test]
+    fn helm_chart_wait_script() {
+        let objects =
+            ManifestObject::from_yaml("apiVersion: helm.cattle.io/v1\nkind: HelmChart\nmetadata:\n  name: web\n")
+                .unwrap();
+
+        let script = objects[0].wait_script();
+        assert!(script.contains(
+            r#"get job.v1.batch/helm-install-web -o jsonpath='{.status.conditions[?(@.status=="True")].type}'"#
+        ));
+        assert!(
+            script.contains("grep -qE 'Complete|Failed'; do sleep 1; done"),
+            "{script}"
+        );
+        assert!(script.ends_with("| grep -q Complete"), "{script}");
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        assert!(matches!(
+            ManifestObject::from_yaml("kind: Pod"),
+            Err(Error::RuntimeConfig(_))
+        ));
+        assert!(matches!(
+            K3s::default().try_with_manifest("pod", "kind: ["),
+            Err(Error::RuntimeConfig(_))
+        ));
+        assert!(matches!(
+            K3s::default().try_with_manifest_dir("/nonexistent-manifests"),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn duplicate_manifest_names() {
+        let k3s = K3s::default().with_manifest("test", MANIFEST);
+        assert!(matches!(
+            k3s.clone().try_with_manifest("test.yaml", MANIFEST),
+            Err(Error::RuntimeConfig(_))
+        ));
+
+        let k3s = k3s.with_manifest("test.yml", MANIFEST);
+        assert_eq!(k3s.manifest_files.len(), 2);
+        assert_eq!(k3s.manifest_files[0].0, "test.yaml");
+    }
+}
@@ -0,0 +1,212 @@
+use super::{block_on_new_runtime, FIELD_MANAGER};
+use crate::{Error, Result};
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{
+    api::{DeleteParams, ObjectMeta, PostParams},
+    runtime::wait::{await_condition, conditions::is_deleted},
+    Api, Client, Config, ResourceExt as _,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use std::{collections::BTreeMap, sync::Mutex, thread, time::Duration};
+
+/// If this environment variable is set, namespaces of the failed (panicked) tests aren't deleted.
+pub const KEEP_FAILED_TEST_NAMESPACES: &str = "CARGO_KEEP_FAILED_TEST_NAMESPACES";
+pub const TEST_NAMESPACE_LABEL: &str = "testcontainers-modules/test-namespace";
+pub const TEST_NAME_ANNOTATION: &str = "testcontainers-modules/test-name";
+pub const K3S_DEFAULT_NAMESPACE_DELETION_TIMEOUT: Duration = Duration::from_secs(120);
+
+const TEST_NAMESPACE_PREFIX: &str = "test-";
+const TEST_NAMESPACE_SUFFIX_LENGTH: usize = 8;
+
+/// Namespaces which are created but not deleted yet, to clean up them in the destructor.
+static TEST_NAMESPACES: Mutex<Vec<(String, Config)>> = Mutex::new(vec![]);
+
+/// Uniquely named namespace which is deleted when guard is dropped.
+pub struct TestNamespace {
+    name: String,
+    client: Client,
+    config: Config,
+    wait_for_deletion: bool,
+    deleted: bool,
+}
+
+impl TestNamespace {
+    /// Creates namespace using provided config, client of the guard uses this namespace as default.
+    pub async fn create(config: Config) -> Result<Self> {
+        let namespace = test_namespace(thread::current().name());
+        let name = namespace.name_any();
+
+        let client = Client::try_from(config.clone())?;
+        let params = PostParams {
+            field_manager: Some(FIELD_MANAGER.to_string()),
+            ..Default::default()
+        };
+        Api::<Namespace>::all(client).create(&params, &namespace).await?;
+        TEST_NAMESPACES.lock().unwrap().push((name.clone(), config.clone()));
+
+        let config = Config {
+            default_namespace: name.clone(),
+            ..config
+        };
+
+        Ok(Self {
+            name,
+            client: Client::try_from(config.clone())?,
+            config,
+            wait_for_deletion: false,
+            deleted: false,
+        })
+    }
+
+    /// Wait until namespace is actually removed (all finalizers are done) during deletion.
+    pub fn with_wait_for_deletion(mut self, wait_for_deletion: bool) -> Self {
+        self.wait_for_deletion = wait_for_deletion;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Client which uses the test namespace as default one.
+    pub fn client(&self) -> Client {
+        self.client.clone()
+    }
+
+    pub async fn delete(mut self) -> Result<()> {
+        self.deleted = true;
+        delete_namespace(self.config.clone(), &self.name, self.wait_for_deletion).await
+    }
+}
+
+impl Drop for TestNamespace {
+    fn drop(&mut self) {
+        if self.deleted {
+            return;
+        }
+
+        if keep_failed_namespace(
+            &self.name,
+            thread::panicking(),
+            std::env::var(KEEP_FAILED_TEST_NAMESPACES).is_ok(),
+        ) {
+            return;
+        }
+
+        let name = self.name.clone();
+        let config = self.config.clone();
+        let wait_for_deletion = self.wait_for_deletion;
+        block_on_new_runtime(async move { delete_namespace(config, &name, wait_for_deletion).await });
+    }
+}
+
+/// Namespace with the random name which is labeled as test one and annotated with the test (thread) name.
+fn test_namespace(test_name: Option<&str>) -> Namespace {
+    let suffix = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TEST_NAMESPACE_SUFFIX_LENGTH)
+        .map(char::from)
+        .collect::<String>()
+        .to_lowercase();
+
+    let mut annotations = BTreeMap::new();
+    if let Some(test_name) = test_name {
+        annotations.insert(TEST_NAME_ANNOTATION.to_string(), test_name.to_string());
+    }
+
+    Namespace {
+        metadata: ObjectMeta {
+            name: Some(format!("{TEST_NAMESPACE_PREFIX}{suffix}")),
+            labels: Some(BTreeMap::from([(TEST_NAMESPACE_LABEL.to_string(), "true".to_string())])),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Keeps namespace of the failed test if it's requested, so it isn't deleted by the destructor either.
+fn keep_failed_namespace(name: &str, panicking: bool, keep_failed: bool) -> bool {
+    if !(panicking && keep_failed) {
+        return false;
+    }
+
+    eprintln!("Test failed, namespace `{name}` is kept");
+    unregister_namespace(name);
+    true
+}
+
+async fn delete_namespace(config: Config, name: &str, wait_for_deletion: bool) -> Result<()> {
+    let api = Api::<Namespace>::all(Client::try_from(config)?);
+    let uid = match api.get_opt(name).await? {
+        Some(namespace) => namespace.uid().unwrap_or_default(),
+        None => {
+            unregister_namespace(name);
+            return Ok(());
+        }
+    };
+
+    api.delete(name, &DeleteParams::default()).await?;
+    unregister_namespace(name);
+
+    if wait_for_deletion {
+        tokio::time::timeout(
+            K3S_DEFAULT_NAMESPACE_DELETION_TIMEOUT,
+            await_condition(api, name, is_deleted(&uid)),
+        )
+        .await
+        .map_err(|_| Error::Timeout(format!("namespace `{name}` isn't deleted")))??;
+    }
+
+    Ok(())
+}
+
+fn unregister_namespace(name: &str) {
+    TEST_NAMESPACES.lock().unwrap().retain(|(n, _)| n != name);
+}
+
+/// Deletes all test namespaces which are still registered, used by the destructor.
+#[cfg(feature = "destructor")]
+pub(crate) async fn delete_test_namespaces() {
+    let namespaces = TEST_NAMESPACES.lock().unwrap().clone();
+    for (name, config) in namespaces {
+        let _ = delete_namespace(config, &name, false).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_metadata() {
+        let namespace = test_namespace(Some("k3s::namespace::tests::test"));
+        let name = namespace.name_any();
+        let suffix = name.strip_prefix(TEST_NAMESPACE_PREFIX).unwrap();
+        assert_eq!(suffix.len(), TEST_NAMESPACE_SUFFIX_LENGTH);
+        assert_eq!(suffix, suffix.to_lowercase());
+        assert_eq!(namespace.labels()[TEST_NAMESPACE_LABEL], "true");
+        assert_eq!(
+            namespace.annotations()[TEST_NAME_ANNOTATION],
+            "k3s::namespace::tests::test"
+        );
+
+        let other = test_namespace(None);
+        assert_ne!(other.name_any(), name);
+        assert!(other.annotations().is_empty());
+    }
+
+    #[test]
+    fn keep_failed_test_namespace() {
+        let config = Config::new("https://127.0.0.1:6443".parse().unwrap());
+        let registered = |name: &str| TEST_NAMESPACES.lock().unwrap().iter().any(|(n, _)| n == name);
+        TEST_NAMESPACES.lock().unwrap().push(("test-kept".to_string(), config));
+
+        assert!(!keep_failed_namespace("test-kept", false, true));
+        assert!(!keep_failed_namespace("test-kept", true, false));
+        assert!(registered("test-kept"));
+
+        assert!(keep_failed_namespace("test-kept", true, true));
+        assert!(!registered("test-kept"));
+    }
+}
@@ -0,0 +1,123 @@
+use super::K3s;
+use crate::{Error, Result};
+use serde_yaml::Value;
+use testcontainers::{
+    bollard::{
+        errors::Error as DockerError,
+        models::{Ipam, IpamConfig},
+        network::CreateNetworkOptions,
+    },
+    core::client::docker_client_instance,
+    TestcontainersError,
+};
+
+/// Docker network with IPv6 enabled to run dual-stack or IPv6-only clusters.
+pub const DOCKER_IPV6_NETWORK_NAME: &str = "testcontainers-ipv6";
+pub const DOCKER_IPV6_NETWORK_SUBNET: &str = "fd00:7e57:c0de::/64";
+
+const IPV4_CLUSTER_CIDR: &str = "10.42.0.0/16";
+const IPV4_SERVICE_CIDR: &str = "10.43.0.0/16";
+const IPV6_CLUSTER_CIDR: &str = "2001:cafe:42::/56";
+const IPV6_SERVICE_CIDR: &str = "2001:cafe:43::/112";
+
+/// IP families of the pod and service networks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum K3sIpFamily {
+    #[default]
+    Ipv4,
+    DualStack,
+    Ipv6,
+}
+
+impl K3sIpFamily {
+    /// Default server settings of the family, explicitly specified settings take precedence.
+    pub(super) fn settings(&self) -> Vec<(&'static str, Value)> {
+        let cidrs = |ipv4: &str, ipv6: &str| match self {
+            K3sIpFamily::Ipv4 => ipv4.to_string(),
+            K3sIpFamily::DualStack => format!("{ipv4},{ipv6}"),
+            K3sIpFamily::Ipv6 => ipv6.to_string(),
+        };
+
+        match self {
+            K3sIpFamily::Ipv4 => vec![],
+            K3sIpFamily::DualStack | K3sIpFamily::Ipv6 => vec![
+                ("cluster-cidr", cidrs(IPV4_CLUSTER_CIDR, IPV6_CLUSTER_CIDR).into()),
+                ("service-cidr", cidrs(IPV4_SERVICE_CIDR, IPV6_SERVICE_CIDR).into()),
+                ("flannel-ipv6-masq", true.into()),
+            ],
+        }
+    }
+
+    /// Whether the host-mapped API server is accessed over IPv6.
+    pub(super) fn is_ipv6_only(&self) -> bool {
+        *self == K3sIpFamily::Ipv6
+    }
+}
+
+impl K3s {
+    /// Configures pod and service CIDRs and flannel for the IP family, container should be attached
+    /// to the network with IPv6 enabled (see [`create_docker_ipv6_network`]) for dual-stack and IPv6-only clusters,
+    /// [`super::K3sCluster`] uses [`DOCKER_IPV6_NETWORK_NAME`] for them by default.
+    /// Explicit `cluster-cidr` and `service-cidr` settings override default ones.
+    pub fn with_ip_family(self, ip_family: K3sIpFamily) -> Self {
+        let mut server_config = self.server_config.clone();
+        server_config.ip_family = ip_family;
+        self.with_server_config(server_config)
+    }
+}
+
+/// Creates Docker network with IPv6 enabled if it doesn't exist yet.
+pub async fn create_docker_ipv6_network(name: &str, subnet: &str) -> Result<()> {
+    let docker = docker_client_instance().await.map_err(TestcontainersError::from)?;
+    match docker.inspect_network::<String>(name, None).await {
+        Ok(network) if network.enable_ipv6 == Some(true) => return Ok(()),
+        Ok(_) => {
+            return Err(Error::RuntimeConfig(format!(
+                "Docker network `{name}` exists but has no IPv6 enabled"
+            )))
+        }
+        Err(DockerError::DockerResponseServerError { status_code: 404, .. }) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let options = CreateNetworkOptions {
+        name,
+        enable_ipv6: true,
+        ipam: Ipam {
+            config: Some(vec![IpamConfig {
+                subnet: Some(subnet.to_string()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    match docker.create_network(options).await {
+        // Network may be created concurrently
+        Ok(_) | Err(DockerError::DockerResponseServerError { status_code: 409, .. }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_family_settings() {
+        assert!(K3sIpFamily::Ipv4.settings().is_empty());
+
+        let settings = K3sIpFamily::DualStack.settings();
+        assert_eq!(settings[0].1, "10.42.0.0/16,2001:cafe:42::/56");
+        assert_eq!(settings[1].1, "10.43.0.0/16,2001:cafe:43::/112");
+
+        let k3s = K3s::default()
+            .with_server_arg("--service-cidr=2001:db8::/112")
+            .with_ip_family(K3sIpFamily::Ipv6);
+        let config = k3s.server_config.render(&k3s.features);
+        assert_eq!(config["cluster-cidr"], "2001:cafe:42::/56");
+        assert_eq!(config["service-cidr"], "2001:db8::/112");
+        assert_eq!(config["flannel-ipv6-masq"], true);
+        assert_eq!(config["tls-san"], serde_yaml::to_value(["::1"]).unwrap());
+    }
+}
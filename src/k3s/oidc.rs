@@ -0,0 +1,104 @@
+use super::{K3s, K3S_CONFIG_FOLDER};
+use testcontainers::{CopyDataSource, CopyToContainer};
+#[cfg(feature = "gitea")]
+use {
+    super::client_for_id_token,
+    crate::{
+        gitea::{Gitea, GiteaOAuth2App},
+        Result,
+    },
+    kube::{Client, Config},
+    testcontainers::ContainerAsync,
+};
+
+const OIDC_CA_FILE_NAME: &str = "oidc-ca.crt";
+
+/// OpenID Connect issuer whose ID tokens are accepted by the API server.
+#[derive(Debug, Clone)]
+pub(crate) struct K3sOidcIssuer {
+    issuer_url: String,
+    client_id: String,
+    ca: Option<String>,
+}
+
+impl K3sOidcIssuer {
+    pub(super) fn files(&self) -> Vec<CopyToContainer> {
+        self.ca
+            .iter()
+            .map(|ca| {
+                CopyToContainer::new(
+                    CopyDataSource::Data(ca.clone().into_bytes()),
+                    format!("{K3S_CONFIG_FOLDER}/{OIDC_CA_FILE_NAME}"),
+                )
+            })
+            .collect()
+    }
+
+    pub(super) fn apiserver_args(&self) -> Vec<String> {
+        let mut args = vec![
+            format!("oidc-issuer-url={}", self.issuer_url),
+            format!("oidc-client-id={}", self.client_id),
+            "oidc-username-claim=preferred_username".to_string(),
+            "oidc-username-prefix=-".to_string(),
+            "oidc-groups-claim=groups".to_string(),
+        ];
+        if self.ca.is_some() {
+            args.push(format!("oidc-ca-file={K3S_CONFIG_FOLDER}/{OIDC_CA_FILE_NAME}"));
+        }
+
+        args
+    }
+}
+
+impl K3s {
+    /// Makes API server accept ID tokens issued to the client by the OIDC issuer, `preferred_username` and
+    /// `groups` claims become user name and groups as is. Issuer should be reachable from the container,
+    /// like Gitea with [`crate::gitea::Gitea::with_oauth2_provider`] on [`crate::DOCKER_NETWORK_NAME`].
+    pub fn with_oidc_issuer(
+        self,
+        issuer_url: impl Into<String>,
+        client_id: impl Into<String>,
+        ca_pem: Option<&str>,
+    ) -> Self {
+        let mut server_config = self.server_config.clone();
+        server_config.oidc_issuer = Some(K3sOidcIssuer {
+            issuer_url: issuer_url.into(),
+            client_id: client_id.into(),
+            ca: ca_pem.map(String::from),
+        });
+        self.with_server_config(server_config)
+    }
+}
+
+/// Gets ID token of the Gitea user for the application and returns client which authenticates with it,
+/// endpoint and CA are taken from the admin config. Gitea should have reverse proxy authentication enabled,
+/// see [`Gitea::id_token`].
+#[cfg(feature = "gitea")]
+pub async fn client_for_gitea_user(
+    config: &Config,
+    gitea: &ContainerAsync<Gitea>,
+    app: &GiteaOAuth2App,
+    username: &str,
+) -> Result<Client> {
+    let id_token = Gitea::id_token(gitea, app, username).await?;
+    client_for_id_token(config, id_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml::Value;
+
+    #[test]
+    fn oidc_issuer_apiserver_args() {
+        let k3s = K3s::default().with_oidc_issuer("https://git-server:3000/", "client", Some("CA"));
+        let config = k3s.server_config.render(&k3s.features);
+        let Value::Sequence(args) = &config["kube-apiserver-arg"] else {
+            panic!("no API server arguments");
+        };
+        assert!(args.contains(&"oidc-issuer-url=https://git-server:3000/".into()));
+        assert!(args.contains(&"oidc-client-id=client".into()));
+        assert!(args.contains(&"oidc-ca-file=/etc/rancher/k3s/oidc-ca.crt".into()));
+        assert_eq!(k3s.server_config.files(&k3s.features).len(), 2);
+    }
+}
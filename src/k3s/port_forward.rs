@@ -0,0 +1,247 @@
+use super::is_pod_ready;
+use crate::{Error, Result};
+use k8s_openapi::{
+    api::core::v1::{Pod, Service},
+    apimachinery::pkg::util::intstr::IntOrString,
+};
+use kube::{api::ListParams, Api, Client, ResourceExt as _};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::{JoinHandle, JoinSet},
+};
+
+/// Pod or Service to forward port to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortForwardTarget {
+    Pod(String),
+    Service(String),
+}
+
+/// Accepts `kubectl`-like targets: `pod/name`, `svc/name`, `service/name` or just pod name.
+impl From<&str> for PortForwardTarget {
+    fn from(value: &str) -> Self {
+        match value.split_once('/') {
+            Some(("svc" | "service" | "services", name)) => Self::Service(name.to_string()),
+            Some(("po" | "pod" | "pods", name)) => Self::Pod(name.to_string()),
+            _ => Self::Pod(value.to_string()),
+        }
+    }
+}
+
+impl From<String> for PortForwardTarget {
+    fn from(value: String) -> Self {
+        Self::from(value.as_str())
+    }
+}
+
+/// Local port which is forwarded to the pod until this guard is dropped.
+#[derive(Debug)]
+pub struct LocalForward {
+    local_addr: SocketAddr,
+    pod: String,
+    pod_port: u16,
+    task: JoinHandle<()>,
+    errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl LocalForward {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.local_addr.port()
+    }
+
+    /// Name of the pod which receives connections, it's resolved once when forwarding starts.
+    pub fn pod(&self) -> &str {
+        &self.pod
+    }
+
+    pub fn pod_port(&self) -> u16 {
+        self.pod_port
+    }
+
+    /// Errors of the forwarded connections so far.
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().unwrap().clone()
+    }
+}
+
+/// Aborts accept loop together with all forwarded connections.
+impl Drop for LocalForward {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Binds ephemeral local port and forwards every accepted connection to the target through the API server,
+/// Service target (and its port) is resolved to a ready backing pod (and its container port).
+pub async fn port_forward(
+    client: &Client,
+    namespace: &str,
+    target: impl Into<PortForwardTarget>,
+    remote_port: u16,
+) -> Result<LocalForward> {
+    let pods: Api<Pod> = Api::namespaced(client.clone(), namespace);
+    let (pod, pod_port) = match target.into() {
+        PortForwardTarget::Pod(pod) => (pod, remote_port),
+        PortForwardTarget::Service(service) => {
+            let service = Api::<Service>::namespaced(client.clone(), namespace)
+                .get(&service)
+                .await?;
+            let pod = ready_service_pod(&pods, &service).await?;
+            let port = service_target_port(&service, &pod, remote_port)?;
+            (pod.name_any(), port)
+        }
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let local_addr = listener.local_addr()?;
+    let errors = Arc::new(Mutex::new(vec![]));
+    let task = tokio::spawn({
+        let pod = pod.clone();
+        let errors = errors.clone();
+        async move {
+            // Connection tasks are aborted when the set is dropped together with the aborted loop
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => {
+                        let Ok((connection, _)) = accepted else {
+                            break;
+                        };
+                        let (pods, pod, errors) = (pods.clone(), pod.clone(), errors.clone());
+                        connections.spawn(async move {
+                            if let Err(e) = forward_connection(&pods, &pod, pod_port, connection).await {
+                                errors
+                                    .lock()
+                                    .unwrap()
+                                    .push(format!("port forward to `{pod}:{pod_port}` failed: {e}"));
+                            }
+                        });
+                    }
+                    Some(_) = connections.join_next() => {}
+                }
+            }
+        }
+    });
+
+    Ok(LocalForward {
+        local_addr,
+        pod,
+        pod_port,
+        task,
+        errors,
+    })
+}
+
+async fn forward_connection(pods: &Api<Pod>, pod: &str, port: u16, mut connection: TcpStream) -> Result<()> {
+    let mut forwarder = pods.portforward(pod, &[port]).await?;
+    let error = forwarder.take_error(port);
+    let mut upstream = forwarder
+        .take_stream(port)
+        .ok_or_else(|| Error::RuntimeConfig(format!("no port forward stream for port {port}")))?;
+
+    let copied = tokio::io::copy_bidirectional(&mut connection, &mut upstream).await;
+    drop(upstream);
+    forwarder
+        .join()
+        .await
+        .map_err(|e| Error::RuntimeConfig(e.to_string()))?;
+    // Error channel is closed when forwarder is finished
+    if let Some(Some(error)) = futures::future::OptionFuture::from(error).await {
+        return Err(Error::RuntimeConfig(error));
+    }
+    copied?;
+
+    Ok(())
+}
+
+async fn ready_service_pod(pods: &Api<Pod>, service: &Service) -> Result<Pod> {
+    let selector = service
+        .spec
+        .as_ref()
+        .and_then(|s| s.selector.as_ref())
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| Error::RuntimeConfig(format!("service `{}` has no selector", service.name_any())))?
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    pods.list(&ListParams::default().labels(&selector))
+        .await?
+        .items
+        .into_iter()
+        .find(|pod| is_pod_ready(Some(pod)))
+        .ok_or_else(|| Error::RuntimeConfig(format!("service `{}` has no ready pods", service.name_any())))
+}
+
+/// Resolves service port to the container port of the pod, named target ports are looked up in the pod spec.
+fn service_target_port(service: &Service, pod: &Pod, port: u16) -> Result<u16> {
+    let service_port = service
+        .spec
+        .as_ref()
+        .and_then(|s| s.ports.as_ref())
+        .and_then(|ports| ports.iter().find(|p| p.port == i32::from(port)))
+        .ok_or_else(|| Error::RuntimeConfig(format!("service `{}` has no port {port}", service.name_any())))?;
+
+    let target_port = match &service_port.target_port {
+        None => Some(service_port.port),
+        Some(IntOrString::Int(port)) => Some(*port),
+        Some(IntOrString::String(name)) => pod
+            .spec
+            .iter()
+            .flat_map(|s| s.containers.iter())
+            .flat_map(|c| c.ports.iter().flatten())
+            .find(|p| p.name.as_ref() == Some(name))
+            .map(|p| p.container_port),
+    };
+
+    target_port
+        .and_then(|p| u16::try_from(p).ok())
+        .ok_or_else(|| Error::RuntimeConfig(format!("can't resolve target port of service port {port}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_port_forward_target() {
+        assert_eq!(PortForwardTarget::from("web-0"), PortForwardTarget::Pod("web-0".into()));
+        assert_eq!(
+            PortForwardTarget::from("pod/web-0"),
+            PortForwardTarget::Pod("web-0".into())
+        );
+        assert_eq!(
+            PortForwardTarget::from("svc/web"),
+            PortForwardTarget::Service("web".into())
+        );
+
+        let service: Service = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "web"},
+            "spec": {"ports": [
+                {"port": 80, "targetPort": "http"},
+                {"port": 8080, "targetPort": 9090},
+                {"port": 443}
+            ]}
+        }))
+        .unwrap();
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "web-0"},
+            "spec": {"containers": [{"name": "web", "ports": [{"name": "http", "containerPort": 8000}]}]}
+        }))
+        .unwrap();
+
+        assert_eq!(service_target_port(&service, &pod, 80).unwrap(), 8000);
+        assert_eq!(service_target_port(&service, &pod, 8080).unwrap(), 9090);
+        assert_eq!(service_target_port(&service, &pod, 443).unwrap(), 443);
+        assert!(service_target_port(&service, &pod, 81).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use super::{
+    wait::{has_condition, is_deployment_rolled_out},
+    K3s, K3sFeatures,
+};
+use crate::{Error, Result};
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment},
+    core::v1::{Node, ServiceAccount},
+};
+use kube::{api::ListParams, runtime::wait::await_condition, Api, Client};
+use std::time::Duration;
+use testcontainers::ContainerAsync;
+
+pub const K3S_DEFAULT_READINESS_TIMEOUT: Duration = Duration::from_secs(180);
+
+const NODES_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const ADDONS_NAMESPACE: &str = "kube-system";
+
+/// Stages of the cluster readiness which are checked using kube API by [`K3s::wait_ready`],
+/// plain container start doesn't check them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum K3sReadinessStage {
+    /// DaemonSets of the CNI manifests are rolled out, see [`K3s::with_cni_manifest`].
+    CniReady,
+    /// All nodes are registered and have `Ready` condition.
+    NodesReady,
+    /// Deployments of the enabled addons (CoreDNS, metrics-server, local-path provisioner, Traefik) are rolled out.
+    AddonsRolledOut,
+    /// Service account `default` exists in the `default` namespace.
+    DefaultServiceAccount,
+}
+
+impl K3sReadinessStage {
+    pub const ALL: [K3sReadinessStage; 4] = [
+        K3sReadinessStage::CniReady,
+        K3sReadinessStage::NodesReady,
+        K3sReadinessStage::AddonsRolledOut,
+        K3sReadinessStage::DefaultServiceAccount,
+    ];
+}
+
+impl K3sFeatures {
+    /// Names of the addon deployments in `kube-system` namespace.
+    fn addon_deployments(&self) -> Vec<&'static str> {
+        let mut deployments = vec![];
+
+        // Nothing can be scheduled without agent
+        if !self.agent {
+            return deployments;
+        }
+        if self.coredns {
+            deployments.push("coredns");
+        }
+        if self.metrics_server {
+            deployments.push("metrics-server");
+        }
+        if self.local_storage {
+            deployments.push("local-path-provisioner");
+        }
+        // Traefik is installed using HelmChart
+        if self.traefik && self.helm_controller {
+            deployments.push("traefik");
+        }
+
+        deployments
+    }
+}
+
+impl K3s {
+    pub fn with_readiness_stages(self, stages: impl IntoIterator<Item = K3sReadinessStage>) -> Self {
+        Self {
+            readiness_stages: stages.into_iter().collect(),
+            ..self
+        }
+    }
+
+    pub fn with_readiness_timeout(self, timeout: Duration) -> Self {
+        Self {
+            readiness_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Waits until all configured readiness stages are passed, it isn't done by the container start itself
+    /// and should be called explicitly, [`super::K3sCluster::start`] does it for the whole cluster.
+    pub async fn wait_ready(container: &ContainerAsync<K3s>) -> Result<()> {
+        let nodes = if container.image().features.agent { 1 } else { 0 };
+        Self::wait_ready_with_nodes(container, nodes).await
+    }
+
+    pub(crate) async fn wait_ready_with_nodes(container: &ContainerAsync<K3s>, nodes: usize) -> Result<()> {
+        let image = container.image();
+        let client = K3s::get_client(container).await?;
+
+        tokio::time::timeout(image.readiness_timeout, async {
+            for stage in &image.readiness_stages {
+                match stage {
+                    K3sReadinessStage::CniReady => {
+                        for (namespace, name) in &image.cni_daemon_sets {
+                            let daemon_sets: Api<DaemonSet> = Api::namespaced(client.clone(), namespace);
+                            await_condition(daemon_sets, name, is_daemon_set_rolled_out).await?;
+                        }
+                    }
+                    K3sReadinessStage::NodesReady => wait_nodes_ready(&client, nodes).await?,
+                    K3sReadinessStage::AddonsRolledOut => {
+                        for deployment in image.features.addon_deployments() {
+                            wait_deployment_rolled_out(&client, ADDONS_NAMESPACE, deployment).await?;
+                        }
+                    }
+                    K3sReadinessStage::DefaultServiceAccount => {
+                        let accounts: Api<ServiceAccount> = Api::namespaced(client.clone(), "default");
+                        await_condition(accounts, "default", |sa: Option<&ServiceAccount>| sa.is_some()).await?;
+                    }
+                }
+            }
+
+            Ok::<_, Error>(())
+        })
+        .await
+        .map_err(|_| Error::Timeout(format!("k3s cluster isn't ready in {:?}", image.readiness_timeout)))?
+    }
+}
+
+async fn wait_nodes_ready(client: &Client, expected: usize) -> Result<()> {
+    let nodes: Api<Node> = Api::all(client.clone());
+    let is_node_ready = has_condition::<Node>("Ready");
+    loop {
+        let list = nodes.list(&ListParams::default()).await?;
+        let ready = list.items.iter().filter(|n| is_node_ready(Some(*n))).count();
+        if ready >= expected && ready == list.items.len() {
+            return Ok(());
+        }
+        tokio::time::sleep(NODES_POLL_INTERVAL).await;
+    }
+}
+
+async fn wait_deployment_rolled_out(client: &Client, namespace: &str, name: &str) -> Result<()> {
+    let deployments: Api<Deployment> = Api::namespaced(client.clone(), namespace);
+    await_condition(deployments, name, is_deployment_rolled_out).await?;
+    Ok(())
+}
+
+/// DaemonSet which isn't scheduled to any node is rolled out as soon as controller observed its spec.
+fn is_daemon_set_rolled_out(daemon_set: Option<&DaemonSet>) -> bool {
+    let Some(status) = daemon_set.and_then(|ds| ds.status.as_ref()) else {
+        return false;
+    };
+
+    let generation = daemon_set.and_then(|ds| ds.metadata.generation).unwrap_or_default();
+    status
+        .observed_generation
+        .is_some_and(|observed| observed >= generation)
+        && status.updated_number_scheduled.unwrap_or_default() == status.desired_number_scheduled
+        && status.number_available.unwrap_or_default() == status.desired_number_scheduled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addon_deployments() {
+        let features = K3sFeatures::default();
+        assert_eq!(
+            features.addon_deployments(),
+            vec!["coredns", "metrics-server", "local-path-provisioner", "traefik"]
+        );
+
+        let features = K3sFeatures {
+            helm_controller: false,
+            metrics_server: false,
+            ..K3sFeatures::default()
+        };
+        assert_eq!(features.addon_deployments(), vec!["coredns", "local-path-provisioner"]);
+
+        let features = K3sFeatures {
+            agent: false,
+            ..K3sFeatures::default()
+        };
+        assert!(features.addon_deployments().is_empty());
+    }
+
+    #[test]
+    fn daemon_set_rollout() {
+        let daemon_set = |status: serde_json::Value| -> DaemonSet {
+            serde_json::from_value(serde_json::json!({"metadata": {"generation": 2}, "status": status})).unwrap()
+        };
+
+        let unobserved = daemon_set(serde_json::json!({
+            "currentNumberScheduled": 0, "desiredNumberScheduled": 0, "numberMisscheduled": 0, "numberReady": 0
+        }));
+        assert!(!is_daemon_set_rolled_out(Some(&unobserved)));
+
+        let unscheduled = daemon_set(serde_json::json!({
+            "observedGeneration": 2,
+            "currentNumberScheduled": 0, "desiredNumberScheduled": 0, "numberMisscheduled": 0, "numberReady": 0
+        }));
+        assert!(is_daemon_set_rolled_out(Some(&unscheduled)));
+
+        let rolling = daemon_set(serde_json::json!({
+            "observedGeneration": 2, "updatedNumberScheduled": 3, "numberAvailable": 2,
+            "currentNumberScheduled": 3, "desiredNumberScheduled": 3, "numberMisscheduled": 0, "numberReady": 2
+        }));
+        assert!(!is_daemon_set_rolled_out(Some(&rolling)));
+        assert!(!is_daemon_set_rolled_out(None));
+    }
+}
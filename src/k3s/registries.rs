@@ -0,0 +1,151 @@
+use super::{K3s, K3S_CONFIG_FOLDER};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use testcontainers::{CopyDataSource, CopyToContainer};
+
+const REGISTRIES_FILE_NAME: &str = "registries.yaml";
+const REGISTRIES_CA_FOLDER: &str = "registries";
+
+/// Content of the containerd registries configuration file `/etc/rancher/k3s/registries.yaml`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct K3sRegistries {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    mirrors: BTreeMap<String, RegistryMirror>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    configs: BTreeMap<String, RegistryConfig>,
+    #[serde(skip)]
+    ca: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct RegistryMirror {
+    endpoint: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct RegistryConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<RegistryAuth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls: Option<RegistryTls>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RegistryAuth {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RegistryTls {
+    ca_file: String,
+}
+
+impl K3sRegistries {
+    fn is_empty(&self) -> bool {
+        self.mirrors.is_empty() && self.configs.is_empty()
+    }
+
+    /// Renders registries config and CA files to copy into the container.
+    pub(crate) fn files(&self) -> Vec<CopyToContainer> {
+        if self.is_empty() {
+            return vec![];
+        }
+
+        let registries = serde_yaml::to_string(self).unwrap();
+        let mut files = vec![CopyToContainer::new(
+            CopyDataSource::Data(registries.into_bytes()),
+            format!("{K3S_CONFIG_FOLDER}/{REGISTRIES_FILE_NAME}"),
+        )];
+        files.extend(self.ca.iter().map(|(host, pem)| {
+            CopyToContainer::new(CopyDataSource::Data(pem.clone().into_bytes()), ca_file_path(host))
+        }));
+
+        files
+    }
+}
+
+fn ca_file_path(host: &str) -> String {
+    let file_name = host.replace(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-', "_");
+    format!("{K3S_CONFIG_FOLDER}/{REGISTRIES_CA_FOLDER}/{file_name}.pem")
+}
+
+impl K3s {
+    /// Adds mirror endpoint (like `http://registry:5000`) for the upstream registry (like `docker.io`),
+    /// use `*` as upstream to mirror all registries.
+    pub fn with_registry_mirror(self, upstream: impl Into<String>, endpoint: impl Into<String>) -> Self {
+        let mut registries = self.registries.clone();
+        registries
+            .mirrors
+            .entry(upstream.into())
+            .or_default()
+            .endpoint
+            .push(endpoint.into());
+
+        self.with_registries(registries)
+    }
+
+    /// Sets credentials to access registry, `host` is registry or mirror endpoint host name with optional port.
+    pub fn with_registry_auth(
+        self,
+        host: impl Into<String>,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        let mut registries = self.registries.clone();
+        registries.configs.entry(host.into()).or_default().auth = Some(RegistryAuth {
+            username: username.into(),
+            password: password.into(),
+        });
+
+        self.with_registries(registries)
+    }
+
+    /// Sets PEM-encoded CA certificate to verify registry TLS certificate.
+    pub fn with_registry_ca(self, host: impl Into<String>, ca_pem: impl Into<String>) -> Self {
+        let host = host.into();
+        let mut registries = self.registries.clone();
+        registries.configs.entry(host.clone()).or_default().tls = Some(RegistryTls {
+            ca_file: ca_file_path(&host),
+        });
+        registries.ca.insert(host, ca_pem.into());
+
+        self.with_registries(registries)
+    }
+
+    fn with_registries(self, registries: K3sRegistries) -> Self {
+        Self {
+            registries_files: registries.files(),
+            registries,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_registries_config() {
+        let k3s = K3s::default()
+            .with_registry_mirror("docker.io", "http://registry:5000")
+            .with_registry_mirror("docker.io", "https://mirror.local")
+            .with_registry_auth("mirror.local", "user", "pass")
+            .with_registry_ca("mirror.local", "PEM");
+
+        let registries: serde_yaml::Value =
+            serde_yaml::from_str(&serde_yaml::to_string(&k3s.registries).unwrap()).unwrap();
+        assert_eq!(
+            registries["mirrors"]["docker.io"]["endpoint"],
+            serde_yaml::from_str::<serde_yaml::Value>("[http://registry:5000, https://mirror.local]").unwrap()
+        );
+        assert_eq!(registries["configs"]["mirror.local"]["auth"]["username"], "user");
+        assert_eq!(
+            registries["configs"]["mirror.local"]["tls"]["ca_file"],
+            "/etc/rancher/k3s/registries/mirror.local.pem"
+        );
+        assert_eq!(k3s.registries_files.len(), 2);
+        assert!(K3s::default().registries_files.is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use super::{exec_cmd, K3s, K3S_CONFIG_FOLDER};
+use crate::{Error, Result};
+use futures::StreamExt as _;
+use testcontainers::{
+    bollard::container::{UploadToContainerOptions, WaitContainerOptions},
+    core::client::docker_client_instance,
+    ContainerAsync, TestcontainersError,
+};
+
+/// Folder where k3s stores etcd snapshots.
+pub const K3S_SNAPSHOTS_FOLDER: &str = "/var/lib/rancher/k3s/server/db/snapshots";
+
+const RESTORE_CONFIG_FILE: &str = "config.yaml.d/99-snapshot-restore.yaml";
+
+impl K3s {
+    /// Saves etcd snapshot with the specified name and returns path to the snapshot file inside the container.
+    /// Cluster should be started with [`K3s::with_cluster_init`] enabled, the shared test cluster
+    /// (see [`crate::get_test_kube_client`]) uses sqlite datastore, so it doesn't support snapshots.
+    pub async fn snapshot(container: &ContainerAsync<K3s>, name: impl AsRef<str>) -> Result<String> {
+        let name = name.as_ref();
+        exec_cmd(
+            container,
+            [
+                "k3s",
+                "etcd-snapshot",
+                "save",
+                "--name",
+                name,
+                "--dir",
+                K3S_SNAPSHOTS_FOLDER,
+            ],
+        )
+        .await?;
+
+        snapshot_path(container, name).await
+    }
+
+    /// Restores cluster state from the latest snapshot with the specified name.
+    ///
+    /// Server is restarted twice: to reset etcd from the snapshot and then normally,
+    /// so clients and host port numbers obtained before restore may be stale.
+    pub async fn restore(container: &ContainerAsync<K3s>, name: impl AsRef<str>) -> Result<()> {
+        let path = snapshot_path(container, name.as_ref()).await?;
+        let docker = docker_client_instance().await.map_err(TestcontainersError::from)?;
+
+        // Start server with cluster reset flags, it restores etcd and exits
+        upload_config(container, restore_config(Some(&path))).await?;
+        container.stop().await?;
+        docker.start_container::<String>(container.id(), None).await?;
+        let mut wait = docker.wait_container(container.id(), None::<WaitContainerOptions<String>>);
+        while let Some(status) = wait.next().await {
+            status?;
+        }
+
+        // Then start it as usual
+        upload_config(container, restore_config(None)).await?;
+        container.start().await?;
+        K3s::wait_ready(container).await
+    }
+}
+
+async fn snapshot_path(container: &ContainerAsync<K3s>, name: &str) -> Result<String> {
+    let list = format!("ls -1t {K3S_SNAPSHOTS_FOLDER}/{name}-* 2>/dev/null | head -n1");
+    let path = exec_cmd(container, ["sh", "-c", list.as_str()]).await?;
+    let path = String::from_utf8_lossy(&path).trim().to_string();
+
+    if path.is_empty() {
+        Err(Error::RuntimeConfig(format!("snapshot `{name}` doesn't exist")))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Config drop-in which resets cluster from the snapshot or turns reset off.
+fn restore_config(snapshot_path: Option<&str>) -> String {
+    let mut config = serde_yaml::Mapping::new();
+    config.insert("cluster-reset".into(), snapshot_path.is_some().into());
+    if let Some(path) = snapshot_path {
+        config.insert("cluster-reset-restore-path".into(), path.into());
+    }
+
+    serde_yaml::to_string(&config).unwrap()
+}
+
+/// Writes k3s config drop-in file, container may be stopped.
+async fn upload_config(container: &ContainerAsync<K3s>, config: String) -> Result<()> {
+    let path = format!("{K3S_CONFIG_FOLDER}/{RESTORE_CONFIG_FILE}");
+    let mut header = tokio_tar::Header::new_gnu();
+    header.set_size(config.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    let mut archive = tokio_tar::Builder::new(Vec::new());
+    archive
+        .append_data(&mut header, path.trim_start_matches('/'), config.as_bytes())
+        .await?;
+    let archive = archive.into_inner().await?;
+
+    let docker = docker_client_instance().await.map_err(TestcontainersError::from)?;
+    let options = UploadToContainerOptions {
+        path: "/".to_string(),
+        ..Default::default()
+    };
+    docker
+        .upload_to_container(container.id(), Some(options), archive.into())
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_restore_config() {
+        let path = format!("{K3S_SNAPSHOTS_FOLDER}/before-k3s-1728000000");
+        assert_eq!(
+            restore_config(Some(&path)),
+            format!("cluster-reset: true\ncluster-reset-restore-path: {path}\n")
+        );
+        assert_eq!(restore_config(None), "cluster-reset: false\n");
+        assert_eq!(
+            format!("{K3S_CONFIG_FOLDER}/{RESTORE_CONFIG_FILE}"),
+            "/etc/rancher/k3s/config.yaml.d/99-snapshot-restore.yaml"
+        );
+    }
+}
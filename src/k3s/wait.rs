@@ -0,0 +1,170 @@
+use crate::{Error, Result};
+use futures::{StreamExt as _, TryStreamExt as _};
+use k8s_openapi::{
+    api::{
+        apps::v1::Deployment,
+        batch::v1::Job,
+        core::v1::{PersistentVolumeClaim, Pod},
+    },
+    NamespaceResourceScope,
+};
+use kube::{
+    runtime::{
+        wait::{self, Condition},
+        watcher::watch_object,
+    },
+    Api, Client, Resource, ResourceExt as _,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Debug, time::Duration};
+
+/// Watches the namespaced object until predicate (like [`is_pod_ready`] or any [`Condition`] of `kube`) holds,
+/// returns the matching object (`None` if it doesn't exist) or [`Error::Timeout`] with YAML of the last seen one.
+pub async fn wait_for<K>(
+    client: &Client,
+    namespace: &str,
+    name: &str,
+    predicate: impl Condition<K>,
+    timeout: Duration,
+) -> Result<Option<K>>
+where
+    K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Serialize + Debug + Send + 'static,
+    K::DynamicType: Default,
+{
+    let api: Api<K> = Api::namespaced(client.clone(), namespace);
+    // Deleted object is kept as the last seen one, it's the most useful state to report
+    let mut last_seen = None;
+    let mut exists = false;
+    let wait = async {
+        let mut objects = watch_object(api, name).boxed();
+        while let Some(object) = objects.try_next().await.map_err(wait::Error::ProbeFailed)? {
+            if predicate.matches_object(object.as_ref()) {
+                return Ok(object);
+            }
+            exists = object.is_some();
+            if object.is_some() {
+                last_seen = object;
+            }
+        }
+        Err(Error::RuntimeConfig(format!(
+            "watch of `{namespace}/{name}` is finished"
+        )))
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(result) => result,
+        Err(_) => {
+            let kind = K::kind(&K::DynamicType::default()).to_string();
+            let deleted = if exists || last_seen.is_none() {
+                ""
+            } else {
+                " (deleted)"
+            };
+            Err(Error::Timeout(format!(
+                "{kind} `{namespace}/{name}` didn't reach expected state in {timeout:?}, last seen{deleted}:\n{}",
+                render_last_seen(last_seen)
+            )))
+        }
+    }
+}
+
+/// Deployment controller observed the latest spec and all replicas are updated and available.
+pub fn is_deployment_rolled_out(deployment: Option<&Deployment>) -> bool {
+    let Some(deployment) = deployment else {
+        return false;
+    };
+    let (Some(spec), Some(status)) = (deployment.spec.as_ref(), deployment.status.as_ref()) else {
+        return false;
+    };
+
+    let replicas = spec.replicas.unwrap_or(1);
+    let generation = deployment.metadata.generation.unwrap_or_default();
+    status.observed_generation.unwrap_or_default() >= generation
+        && status.updated_replicas.unwrap_or_default() == replicas
+        && status.available_replicas.unwrap_or_default() == replicas
+}
+
+/// Pod isn't terminating and has `Ready` condition.
+pub fn is_pod_ready(pod: Option<&Pod>) -> bool {
+    pod.is_some_and(|pod| pod.metadata.deletion_timestamp.is_none()) && has_condition("Ready")(pod)
+}
+
+/// Job has `Complete` condition.
+pub fn is_job_completed(job: Option<&Job>) -> bool {
+    has_condition("Complete")(job)
+}
+
+/// PersistentVolumeClaim is in `Bound` phase.
+pub fn is_pvc_bound(pvc: Option<&PersistentVolumeClaim>) -> bool {
+    pvc.and_then(|pvc| pvc.status.as_ref())
+        .and_then(|status| status.phase.as_deref())
+        == Some("Bound")
+}
+
+/// Object has `status.conditions` item of the type with `True` status, it works for custom resources too.
+pub fn has_condition<K: Serialize>(condition_type: &str) -> impl Fn(Option<&K>) -> bool + '_ {
+    move |object| {
+        let Some(status) = object
+            .and_then(|o| serde_json::to_value(o).ok())
+            .map(|o| o["status"].clone())
+        else {
+            return false;
+        };
+        status["conditions"]
+            .as_array()
+            .is_some_and(|c| c.iter().any(|c| c["type"] == condition_type && c["status"] == "True"))
+    }
+}
+
+/// Object doesn't exist (anymore).
+pub fn is_deleted<K>(object: Option<&K>) -> bool {
+    object.is_none()
+}
+
+fn render_last_seen<K: Resource + Serialize>(object: Option<K>) -> String {
+    match object {
+        Some(mut object) => {
+            object.managed_fields_mut().clear();
+            serde_yaml::to_string(&object).unwrap_or_default()
+        }
+        None => "<none>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_predicates() {
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "web-0", "managedFields": [{"manager": "kubectl"}]},
+            "status": {"conditions": [{"type": "Ready", "status": "True"}]}
+        }))
+        .unwrap();
+        assert!(is_pod_ready(Some(&pod)));
+        assert!(!has_condition("Initialized")(Some(&pod)));
+        assert!(!is_pod_ready(None));
+        assert!(!is_deleted(Some(&pod)));
+        assert!(!render_last_seen(Some(pod)).contains("kubectl"));
+        assert_eq!(render_last_seen::<Pod>(None), "<none>");
+
+        let job: Job = serde_json::from_value(serde_json::json!({
+            "status": {"conditions": [{"type": "Complete", "status": "True"}]}
+        }))
+        .unwrap();
+        assert!(is_job_completed(Some(&job)));
+
+        let pvc: PersistentVolumeClaim =
+            serde_json::from_value(serde_json::json!({"status": {"phase": "Pending"}})).unwrap();
+        assert!(!is_pvc_bound(Some(&pvc)));
+
+        let deployment: Deployment = serde_json::from_value(serde_json::json!({
+            "metadata": {"generation": 2},
+            "spec": {"replicas": 2, "selector": {}, "template": {}},
+            "status": {"observedGeneration": 2, "updatedReplicas": 2, "availableReplicas": 1}
+        }))
+        .unwrap();
+        assert!(!is_deployment_rolled_out(Some(&deployment)));
+    }
+}
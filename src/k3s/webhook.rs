@@ -0,0 +1,363 @@
+use super::{block_on_new_runtime, FIELD_MANAGER};
+use crate::{tls, tls::TlsCert, Result};
+use http::{header::CONTENT_TYPE, Request, Response};
+use http_body_util::{BodyExt as _, Full};
+use hyper::{
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use k8s_openapi::{
+    api::admissionregistration::v1::{
+        MutatingWebhook, MutatingWebhookConfiguration, RuleWithOperations, ValidatingWebhook,
+        ValidatingWebhookConfiguration, WebhookClientConfig,
+    },
+    apimachinery::pkg::apis::meta::v1::LabelSelector,
+    ByteString,
+};
+use kube::{
+    api::{DeleteParams, ObjectMeta, Patch, PatchParams},
+    core::{
+        admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+        DynamicObject,
+    },
+    Api, Client, Config,
+};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::{
+    net::TcpListener,
+    task::{JoinHandle, JoinSet},
+};
+use tokio_rustls::TlsAcceptor;
+
+/// Host name of the Docker host inside k3s containers started by this module (mapped to `host-gateway`),
+/// add it to the custom containers with `.with_host(K3S_HOST_GATEWAY_ALIAS, Host::HostGateway)`.
+pub const K3S_HOST_GATEWAY_ALIAS: &str = "host.docker.internal";
+
+const WEBHOOK_CA_NAME: &str = "admission webhook CA";
+const WEBHOOK_NAME_SUFFIX: &str = "webhook.testcontainers.local";
+const WEBHOOK_TIMEOUT_SECONDS: i32 = 10;
+const NAMESPACE_NAME_LABEL: &str = "kubernetes.io/metadata.name";
+
+type AdmissionHandler = dyn Fn(&AdmissionRequest<DynamicObject>) -> AdmissionResponse + Send + Sync;
+
+/// Kind of the admission webhook configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum K3sAdmissionWebhookKind {
+    Validating,
+    Mutating,
+}
+
+/// Admission webhook which is served from the test process, see [`K3sAdmissionWebhook::serve`].
+#[derive(Debug, Clone)]
+pub struct K3sAdmissionWebhook {
+    name: String,
+    kind: K3sAdmissionWebhookKind,
+    rules: Vec<RuleWithOperations>,
+    namespace: Option<String>,
+    failure_policy: String,
+}
+
+impl K3sAdmissionWebhook {
+    /// Webhook with `Fail` failure policy and without rules, configuration is named `name`.
+    pub fn new(name: impl Into<String>, kind: K3sAdmissionWebhookKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            rules: vec![],
+            namespace: None,
+            failure_policy: "Fail".to_string(),
+        }
+    }
+
+    pub fn validating(name: impl Into<String>) -> Self {
+        Self::new(name, K3sAdmissionWebhookKind::Validating)
+    }
+
+    pub fn mutating(name: impl Into<String>) -> Self {
+        Self::new(name, K3sAdmissionWebhookKind::Mutating)
+    }
+
+    /// Adds operations and resources to intercept, like `CREATE` of `apps/v1` `deployments`.
+    pub fn with_rule(self, rule: RuleWithOperations) -> Self {
+        let mut rules = self.rules;
+        rules.push(rule);
+        Self { rules, ..self }
+    }
+
+    /// Limits webhook to the objects of the namespace (like [`super::TestNamespace`]), so other tests
+    /// aren't affected.
+    pub fn with_namespace(self, namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: Some(namespace.into()),
+            ..self
+        }
+    }
+
+    /// Sets `Fail` (default) or `Ignore` failure policy.
+    pub fn with_failure_policy(self, failure_policy: impl Into<String>) -> Self {
+        Self {
+            failure_policy: failure_policy.into(),
+            ..self
+        }
+    }
+
+    /// Starts HTTPS listener on the ephemeral port of the host and registers webhook configuration which
+    /// points API server to it through [`K3S_HOST_GATEWAY_ALIAS`], every admission request is answered
+    /// by the handler.
+    pub async fn serve<F>(self, config: Config, handler: F) -> Result<AdmissionWebhookServer>
+    where
+        F: Fn(&AdmissionRequest<DynamicObject>) -> AdmissionResponse + Send + Sync + 'static,
+    {
+        let cert = TlsCert::new(WEBHOOK_CA_NAME, [K3S_HOST_GATEWAY_ALIAS]);
+        let acceptor = TlsAcceptor::from(Arc::new(tls::server_config(&cert)?));
+
+        // API server reaches the host through the Docker bridge, not the loopback interface
+        let listener = TcpListener::bind(("0.0.0.0", 0)).await?;
+        let port = listener.local_addr()?.port();
+        let handler: Arc<AdmissionHandler> = Arc::new(handler);
+        let task = tokio::spawn(async move {
+            // Connection tasks are aborted when the set is dropped together with the aborted loop
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => {
+                        let Ok((connection, _)) = accepted else {
+                            break;
+                        };
+                        let (acceptor, handler) = (acceptor.clone(), handler.clone());
+                        connections.spawn(async move {
+                            let Ok(connection) = acceptor.accept(connection).await else {
+                                return;
+                            };
+                            let service = service_fn(|request| review(request, handler.clone()));
+                            let _ = http1::Builder::new()
+                                .serve_connection(TokioIo::new(connection), service)
+                                .await;
+                        });
+                    }
+                    Some(_) = connections.join_next() => {}
+                }
+            }
+        });
+
+        let server = AdmissionWebhookServer {
+            name: self.name.clone(),
+            kind: self.kind,
+            port,
+            config: config.clone(),
+            task,
+        };
+        let url = format!("https://{K3S_HOST_GATEWAY_ALIAS}:{port}/");
+        let ca = cert.ca().unwrap_or_default();
+        self.register(Client::try_from(config)?, &url, ca).await?;
+
+        Ok(server)
+    }
+
+    async fn register(&self, client: Client, url: &str, ca: &str) -> Result<()> {
+        let params = PatchParams::apply(FIELD_MANAGER).force();
+        match self.kind {
+            K3sAdmissionWebhookKind::Validating => {
+                let configuration = self.validating_configuration(url, ca);
+                Api::<ValidatingWebhookConfiguration>::all(client)
+                    .patch(&self.name, &params, &Patch::Apply(configuration))
+                    .await?;
+            }
+            K3sAdmissionWebhookKind::Mutating => {
+                let configuration = self.mutating_configuration(url, ca);
+                Api::<MutatingWebhookConfiguration>::all(client)
+                    .patch(&self.name, &params, &Patch::Apply(configuration))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validating_configuration(&self, url: &str, ca: &str) -> ValidatingWebhookConfiguration {
+        ValidatingWebhookConfiguration {
+            metadata: self.metadata(),
+            webhooks: Some(vec![ValidatingWebhook {
+                name: self.webhook_name(),
+                client_config: client_config(url, ca),
+                rules: Some(self.rules.clone()),
+                namespace_selector: self.namespace_selector(),
+                failure_policy: Some(self.failure_policy.clone()),
+                side_effects: "None".to_string(),
+                admission_review_versions: vec!["v1".to_string()],
+                timeout_seconds: Some(WEBHOOK_TIMEOUT_SECONDS),
+                ..Default::default()
+            }]),
+        }
+    }
+
+    fn mutating_configuration(&self, url: &str, ca: &str) -> MutatingWebhookConfiguration {
+        MutatingWebhookConfiguration {
+            metadata: self.metadata(),
+            webhooks: Some(vec![MutatingWebhook {
+                name: self.webhook_name(),
+                client_config: client_config(url, ca),
+                rules: Some(self.rules.clone()),
+                namespace_selector: self.namespace_selector(),
+                failure_policy: Some(self.failure_policy.clone()),
+                side_effects: "None".to_string(),
+                admission_review_versions: vec!["v1".to_string()],
+                timeout_seconds: Some(WEBHOOK_TIMEOUT_SECONDS),
+                ..Default::default()
+            }]),
+        }
+    }
+
+    fn metadata(&self) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(self.name.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Webhook names should be fully qualified.
+    fn webhook_name(&self) -> String {
+        format!("{}.{WEBHOOK_NAME_SUFFIX}", self.name)
+    }
+
+    fn namespace_selector(&self) -> Option<LabelSelector> {
+        self.namespace.as_ref().map(|namespace| LabelSelector {
+            match_labels: Some(BTreeMap::from([(NAMESPACE_NAME_LABEL.to_string(), namespace.clone())])),
+            ..Default::default()
+        })
+    }
+}
+
+/// Running webhook server, configuration is deleted and listener is stopped when guard is dropped.
+pub struct AdmissionWebhookServer {
+    name: String,
+    kind: K3sAdmissionWebhookKind,
+    port: u16,
+    config: Config,
+    task: JoinHandle<()>,
+}
+
+impl AdmissionWebhookServer {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Host port of the HTTPS listener.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for AdmissionWebhookServer {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        let name = self.name.clone();
+        let kind = self.kind;
+        let config = self.config.clone();
+        block_on_new_runtime(async move { delete_configuration(config, kind, &name).await });
+    }
+}
+
+async fn delete_configuration(config: Config, kind: K3sAdmissionWebhookKind, name: &str) -> Result<()> {
+    let client = Client::try_from(config)?;
+    let params = DeleteParams::default();
+    match kind {
+        K3sAdmissionWebhookKind::Validating => {
+            Api::<ValidatingWebhookConfiguration>::all(client)
+                .delete(name, &params)
+                .await?;
+        }
+        K3sAdmissionWebhookKind::Mutating => {
+            Api::<MutatingWebhookConfiguration>::all(client)
+                .delete(name, &params)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn client_config(url: &str, ca: &str) -> WebhookClientConfig {
+    WebhookClientConfig {
+        url: Some(url.to_string()),
+        ca_bundle: Some(ByteString(ca.as_bytes().to_vec())),
+        service: None,
+    }
+}
+
+async fn review(
+    request: Request<Incoming>,
+    handler: Arc<AdmissionHandler>,
+) -> std::result::Result<Response<Full<Bytes>>, hyper::Error> {
+    let body = request.into_body().collect().await?.to_bytes();
+    let response = Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(handle_review(&body, handler.as_ref()))))
+        .unwrap();
+
+    Ok(response)
+}
+
+/// Answers `AdmissionReview` request, malformed requests are rejected as invalid.
+fn handle_review(body: &[u8], handler: &AdmissionHandler) -> Vec<u8> {
+    let request = serde_json::from_slice::<AdmissionReview<DynamicObject>>(body)
+        .map_err(|e| e.to_string())
+        .and_then(|review| {
+            let request: std::result::Result<AdmissionRequest<DynamicObject>, _> = review.try_into();
+            request.map_err(|e| e.to_string())
+        });
+    let response = match request {
+        Ok(request) => handler(&request),
+        Err(e) => AdmissionResponse::invalid(e),
+    };
+
+    serde_json::to_vec(&response.into_review()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REVIEW: &str = r#"{
+  "apiVersion": "admission.k8s.io/v1",
+  "kind": "AdmissionReview",
+  "request": {
+    "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+    "kind": {"group": "", "version": "v1", "kind": "ConfigMap"},
+    "resource": {"group": "", "version": "v1", "resource": "configmaps"},
+    "name": "settings",
+    "namespace": "apps",
+    "operation": "CREATE",
+    "userInfo": {"username": "admin"},
+    "object": {"apiVersion": "v1", "kind": "ConfigMap", "metadata": {"name": "settings"}},
+    "dryRun": false
+  }
+}"#;
+
+    #[test]
+    fn answer_admission_review() {
+        let deny = |request: &AdmissionRequest<DynamicObject>| {
+            AdmissionResponse::from(request).deny(format!("{} is denied", request.name))
+        };
+
+        let response: serde_json::Value = serde_json::from_slice(&handle_review(REVIEW.as_bytes(), &deny)).unwrap();
+        assert_eq!(response["response"]["uid"], "705ab4f5-6393-11e8-b7cc-42010a800002");
+        assert_eq!(response["response"]["allowed"], false);
+        assert_eq!(response["response"]["status"]["message"], "settings is denied");
+
+        let response: serde_json::Value = serde_json::from_slice(&handle_review(b"{}", &deny)).unwrap();
+        assert_eq!(response["response"]["allowed"], false);
+
+        let webhook = K3sAdmissionWebhook::validating("deny-configmaps").with_namespace("apps");
+        let configuration = webhook.validating_configuration("https://host.docker.internal:8443/", "CA");
+        let hook = &configuration.webhooks.unwrap()[0];
+        assert_eq!(hook.name, "deny-configmaps.webhook.testcontainers.local");
+        assert_eq!(
+            hook.namespace_selector.as_ref().unwrap().match_labels.as_ref().unwrap()[NAMESPACE_NAME_LABEL],
+            "apps"
+        );
+    }
+}
@@ -4,6 +4,8 @@
 pub mod gitea;
 #[cfg(feature = "k3s")]
 pub mod k3s;
+#[cfg(any(feature = "k3s", feature = "gitea"))]
+pub mod tls;
 
 use thiserror::Error;
 
@@ -12,9 +14,9 @@ use ctor::dtor;
 #[cfg(feature = "gitea")]
 use gitea::Gitea;
 #[cfg(feature = "k3s")]
-use k3s::K3s;
+use k3s::{K3s, TestNamespace};
 #[cfg(feature = "k3s")]
-use kube::Client;
+use kube::{Client, Config};
 #[cfg(feature = "k3s")]
 use rustls::crypto::{aws_lc_rs, CryptoProvider};
 #[cfg(any(feature = "k3s", feature = "gitea"))]
@@ -22,7 +24,9 @@ use std::env;
 #[cfg(all(feature = "destructor", any(feature = "k3s", feature = "gitea")))]
 use std::thread;
 #[cfg(any(feature = "k3s", feature = "gitea"))]
-use testcontainers::ContainerAsync;
+use std::time::Duration;
+#[cfg(any(feature = "k3s", feature = "gitea"))]
+use testcontainers::{core::ExecCommand, ContainerAsync, Image};
 #[cfg(all(feature = "destructor", any(feature = "k3s", feature = "gitea")))]
 use tokio::runtime;
 #[cfg(any(feature = "k3s", feature = "gitea"))]
@@ -33,6 +37,9 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 pub const DOCKER_NETWORK_NAME: &str = "testcontainers";
 
+#[cfg(any(feature = "k3s", feature = "gitea"))]
+const EXEC_EXIT_CODE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[cfg(feature = "k3s")]
 const USE_EXISTING_K8S_CONTEXT: &str = "CARGO_USE_EXISTING_K8S_CONTEXT";
 
@@ -59,6 +66,46 @@ pub enum Error {
     #[error("Kube error: {0}")]
     KubeConfig(#[from] kube::config::KubeconfigError),
 
+    #[cfg(feature = "k3s")]
+    /// Error during inferring of kube config.
+    #[error("Kube error: {0}")]
+    KubeInferConfig(#[from] kube::config::InferConfigError),
+
+    #[cfg(feature = "k3s")]
+    /// Error during waiting for kube resource condition.
+    #[error("Kube wait error: {0}")]
+    KubeWait(#[from] kube::runtime::wait::Error),
+
+    #[cfg(feature = "k3s")]
+    /// Helm chart installation job failed.
+    #[error("Helm chart `{chart}` installation failed, job logs:\n{logs}")]
+    HelmInstall { chart: String, logs: String },
+
+    #[cfg(feature = "k3s")]
+    /// Operation didn't finish in time.
+    #[error("Timeout: {0}")]
+    Timeout(String),
+
+    #[cfg(feature = "k3s")]
+    /// Error during Docker API calls.
+    #[error("Docker error: {0}")]
+    Docker(#[from] testcontainers::bollard::errors::Error),
+
+    #[cfg(feature = "k3s")]
+    /// Error during HTTP request.
+    #[error("HTTP error: {0}")]
+    Http(#[from] hyper::Error),
+
+    #[cfg(feature = "k3s")]
+    /// Invalid HTTP request.
+    #[error("HTTP request error: {0}")]
+    HttpRequest(#[from] http::Error),
+
+    #[cfg(feature = "k3s")]
+    /// TLS configuration error.
+    #[error("TLS error: {0}")]
+    Tls(#[from] rustls::Error),
+
     #[cfg(feature = "destructor")]
     /// Error during tokio operations.
     #[error("Tokio error: {0}")]
@@ -68,6 +115,11 @@ pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[cfg(any(feature = "k3s", feature = "gitea"))]
+    /// Command executed inside a container finished with non-zero exit code.
+    #[error("Command `{command}` failed with exit code {code}: {stderr}")]
+    ContainerExec { command: String, code: i64, stderr: String },
+
     /// Runtime configuration error
     #[error("Runtime configuration error: {0}")]
     RuntimeConfig(String),
@@ -88,15 +140,28 @@ pub async fn get_test_gitea_hostname() -> Result<String> {
 
 #[cfg(feature = "k3s")]
 pub async fn get_test_kube_client() -> Result<Client> {
+    let config = get_test_kube_config().await?;
+    Ok(Client::try_from(config)?)
+}
+
+/// Creates uniquely named namespace for the test, it's deleted when returned guard is dropped.
+#[cfg(feature = "k3s")]
+pub async fn get_test_namespace() -> Result<TestNamespace> {
+    let config = get_test_kube_config().await?;
+    TestNamespace::create(config).await
+}
+
+#[cfg(feature = "k3s")]
+async fn get_test_kube_config() -> Result<Config> {
     if std::env::var(USE_EXISTING_K8S_CONTEXT).is_ok() {
         init_crypto_provider();
-        let client = Client::try_default().await?;
-        return Ok(client);
+        let config = Config::infer().await?;
+        return Ok(config);
     }
 
     let guard = start_k3s_cluster().await.read().await;
     let cluster = guard.as_ref().unwrap();
-    K3s::get_client(cluster).await
+    K3s::get_config(cluster).await
 }
 
 #[cfg(feature = "gitea")]
@@ -128,6 +193,35 @@ fn get_runtime_folder() -> Result<String> {
         .map_err(|_| Error::RuntimeConfig("`OUT_DIR` environment variable isn`t set, use Cargo to run build".into()))
 }
 
+#[cfg(any(feature = "k3s", feature = "gitea"))]
+/// Runs command inside the container and returns its stdout, non-zero exit code is an error.
+pub(crate) async fn exec_cmd<I: Image>(
+    container: &ContainerAsync<I>,
+    cmd: impl IntoIterator<Item = impl Into<String>>,
+) -> Result<Vec<u8>> {
+    let cmd = cmd.into_iter().map(Into::into).collect::<Vec<String>>();
+    let mut result = container.exec(ExecCommand::new(cmd.clone())).await?;
+    let stdout = result.stdout_to_vec().await?;
+    let stderr = result.stderr_to_vec().await?;
+
+    let code = loop {
+        if let Some(code) = result.exit_code().await? {
+            break code;
+        }
+        tokio::time::sleep(EXEC_EXIT_CODE_POLL_INTERVAL).await;
+    };
+
+    if code == 0 {
+        Ok(stdout)
+    } else {
+        Err(Error::ContainerExec {
+            command: cmd.join(" "),
+            code,
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        })
+    }
+}
+
 #[cfg(feature = "k3s")]
 fn init_crypto_provider() {
     if CryptoProvider::get_default().is_none() {
@@ -147,6 +241,9 @@ fn shutdown_test_containers() {
         runtime::Runtime::new().unwrap().block_on(async {
             let _guard = LOCK.lock().await;
 
+            #[cfg(feature = "k3s")]
+            k3s::delete_test_namespaces().await;
+
             #[cfg(feature = "k3s")]
             if let Some(k3s) = K3S_CLUSTER_CONTAINER.get() {
                 let mut k3s = k3s.write().await;
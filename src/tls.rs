@@ -0,0 +1,89 @@
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+#[cfg(feature = "k3s")]
+use rustls::{
+    pki_types::{pem::PemObject as _, CertificateDer, PrivateKeyDer},
+    ClientConfig, RootCertStore, ServerConfig,
+};
+
+/// PEM-encoded certificate with private key and CA certificate if it was generated.
+#[derive(Debug, Clone)]
+pub struct TlsCert {
+    pub(crate) cert: String,
+    pub(crate) key: String,
+    pub(crate) ca: Option<String>,
+}
+
+impl TlsCert {
+    /// Generates self-signed CA and certificate for the host names (or IP addresses) signed by this CA.
+    pub fn new(ca_name: impl Into<String>, hostnames: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_cert = CertificateParams::new(vec![ca_name.into()]).unwrap();
+        ca_cert.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_cert.self_signed(&ca_key).unwrap();
+
+        let hostnames = hostnames.into_iter().map(Into::into).collect::<Vec<String>>();
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(hostnames)
+            .unwrap()
+            .signed_by(&key, &ca_cert, &ca_key)
+            .unwrap();
+
+        Self {
+            cert: cert.pem(),
+            key: key.serialize_pem(),
+            ca: Some(ca_cert.pem()),
+        }
+    }
+
+    pub fn from_pem(cert: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+            ca: None,
+        }
+    }
+
+    pub fn cert(&self) -> &str {
+        &self.cert
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn ca(&self) -> Option<&str> {
+        self.ca.as_deref()
+    }
+}
+
+/// TLS client config which trusts only certificates signed by the specified PEM-encoded CA.
+#[cfg(feature = "k3s")]
+pub(crate) fn client_config(ca_pem: &str) -> crate::Result<ClientConfig> {
+    crate::init_crypto_provider();
+
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(ca_pem.as_bytes()) {
+        let cert = cert.map_err(|e| crate::Error::RuntimeConfig(format!("invalid CA certificate: {e}")))?;
+        roots.add(cert)?;
+    }
+
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// TLS server config which presents the PEM-encoded certificate.
+#[cfg(feature = "k3s")]
+pub(crate) fn server_config(cert: &TlsCert) -> crate::Result<ServerConfig> {
+    crate::init_crypto_provider();
+
+    let invalid = |e| crate::Error::RuntimeConfig(format!("invalid server certificate: {e}"));
+    let chain = CertificateDer::pem_slice_iter(cert.cert.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    let key = PrivateKeyDer::from_pem_slice(cert.key.as_bytes()).map_err(invalid)?;
+
+    Ok(ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?)
+}